@@ -0,0 +1,118 @@
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use anyhow::Context;
+use frame_ocr::frame_cache::FrameCache;
+use frame_ocr::output::{OcrResult, OutputFormat};
+use frame_ocr::pipeline::OcrOptions;
+use frame_ocr::roi::Roi;
+use frame_ocr::sequence::SpanTracker;
+use frame_ocr::OcrService;
+use rten_tensor::NdTensor;
+use tracing::info;
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::health::{Readiness, ReadyState};
+use crate::metrics;
+use crate::video::{FrameReader, FrameSampling, VideoFrameResult, VideoResponse};
+
+lazy_static! {
+    pub static ref CONFIG: Config = Config::load().expect("Invalid configuration");
+
+    /// Set once the models have been loaded by [load_models].
+    static ref SERVICE: OnceLock<OcrService> = OnceLock::new();
+
+    pub static ref READINESS: Readiness = Readiness::default();
+}
+
+/// Load the models and check them with a self-test, recording the outcome
+/// in [READINESS].
+pub fn load_models() -> Result<&'static OcrService, anyhow::Error> {
+    info!("Loading models");
+    let start = Instant::now();
+    let result = OcrService::new(&CONFIG.engine_config()).and_then(|service| {
+        // Only make the service available to requests once it has passed the
+        // self-test.
+        service.self_test().context("Self-test failed")?;
+        let frame_cache = FrameCache::new(CONFIG.frame_cache_size, CONFIG.frame_cache_distance);
+        Ok(SERVICE.get_or_init(|| service.with_frame_cache(frame_cache)))
+    });
+    match &result {
+        Ok(_) => {
+            let load_time = start.elapsed();
+            metrics::set_model_load_time(load_time);
+            READINESS.set(ReadyState::Ready { load_time });
+        }
+        Err(err) => READINESS.set(ReadyState::Failed(format!("{:#}", err))),
+    }
+    result
+}
+
+/// Return the service, once the models are loaded.
+pub fn service() -> Result<&'static OcrService, ApiError> {
+    SERVICE.get().ok_or(ApiError::NotReady {
+        retry_after: CONFIG.retry_after,
+    })
+}
+
+pub fn new_span_tracker() -> SpanTracker {
+    SpanTracker::new(CONFIG.dedupe_similarity, CONFIG.dedupe_overlap)
+}
+
+/// Decode an image and run OCR on it.
+///
+/// This is CPU-bound, so it should be run on the compute pool.
+pub fn ocr_image(
+    data: &[u8],
+    content_type: Option<&str>,
+    regions: Option<&[Roi]>,
+    opts: &OcrOptions,
+) -> Result<OcrResult, ApiError> {
+    let result = service()?.ocr_image(data, content_type, regions, opts)?;
+    metrics::observe_image(&result);
+    Ok(result)
+}
+
+/// Run OCR on an HWC RGB image.
+pub fn ocr_tensor(
+    color_img: &NdTensor<u8, 3>,
+    regions: Option<&[Roi]>,
+    opts: &OcrOptions,
+) -> Result<OcrResult, ApiError> {
+    let result = service()?.ocr_tensor(color_img, regions, opts)?;
+    metrics::observe_image(&result);
+    Ok(result)
+}
+
+/// Sample frames from a video file and run OCR on each of them.
+///
+/// If `spans` is given, lines repeated across consecutive frames are also
+/// merged into spans.
+pub fn ocr_video(
+    path: &Path,
+    sampling: FrameSampling,
+    format: OutputFormat,
+    regions: Option<&[Roi]>,
+    opts: &OcrOptions,
+    mut spans: Option<SpanTracker>,
+) -> Result<VideoResponse, ApiError> {
+    let mut frames = Vec::new();
+    for frame in FrameReader::open(&CONFIG.ffmpeg, path, sampling, CONFIG.max_video_frames)? {
+        let frame = frame?;
+        let result = ocr_tensor(&frame.image, regions, opts)?;
+        if let Some(spans) = &mut spans {
+            spans.push_frame(frame.index, Some(frame.timestamp), &result.lines);
+        }
+        frames.push(VideoFrameResult {
+            index: frame.index,
+            timestamp: frame.timestamp,
+            output: result.format(format),
+        });
+    }
+    Ok(VideoResponse {
+        frames,
+        spans: spans.map(SpanTracker::finish),
+    })
+}
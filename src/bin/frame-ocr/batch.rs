@@ -0,0 +1,130 @@
+use actix_multipart::Multipart;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use frame_ocr::output::FormattedOutput;
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ApiError, ErrorBody};
+use frame_ocr::sequence::TextSpan;
+
+/// An image submitted to the batch endpoint.
+pub struct BatchItem {
+    /// Multipart field name, or the name given in the JSON body. Defaults to
+    /// the item's index.
+    pub name: String,
+
+    /// Image data, or the reason it could not be read. Errors are reported
+    /// for the item instead of failing the whole batch.
+    pub data: Result<Vec<u8>, ApiError>,
+    pub content_type: Option<String>,
+}
+
+/// Entry in a JSON batch body: either a bare base64 string or an object with
+/// a name.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonBatchItem {
+    Data(String),
+    Named { name: Option<String>, data: String },
+}
+
+/// Parse a JSON array of base64-encoded images.
+pub fn parse_json_batch(body: &[u8]) -> Result<Vec<BatchItem>, ApiError> {
+    let items: Vec<JsonBatchItem> = serde_json::from_slice(body)
+        .map_err(|err| ApiError::InvalidRequest(format!("Invalid batch body: {}", err)))?;
+    let items = items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let (name, data) = match item {
+                JsonBatchItem::Data(data) => (None, data),
+                JsonBatchItem::Named { name, data } => (name, data),
+            };
+            let name = name.unwrap_or_else(|| index.to_string());
+            let data = BASE64
+                .decode(data.trim())
+                .map_err(|err| ApiError::InvalidImage(format!("Invalid base64 data: {}", err)));
+            BatchItem {
+                name,
+                data,
+                content_type: None,
+            }
+        })
+        .collect();
+    Ok(items)
+}
+
+/// Read every part of a multipart body as an image, enforcing `max_size`
+/// bytes across all parts.
+pub async fn read_multipart_batch(
+    mut multipart: Multipart,
+    max_size: usize,
+) -> Result<Vec<BatchItem>, ApiError> {
+    let invalid_body = |err| ApiError::InvalidRequest(format!("Invalid multipart body: {}", err));
+
+    let mut items = Vec::new();
+    let mut total_size = 0;
+    while let Some(mut field) = multipart.try_next().await.map_err(invalid_body)? {
+        let name = field
+            .name()
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| items.len().to_string());
+        let content_type = field.content_type().map(|mime| mime.to_string());
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field.try_next().await.map_err(invalid_body)? {
+            total_size += chunk.len();
+            if total_size > max_size {
+                return Err(ApiError::PayloadTooLarge { limit: max_size });
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        items.push(BatchItem {
+            name,
+            data: Ok(data),
+            content_type,
+        });
+    }
+    Ok(items)
+}
+
+/// Result for one item of a batch. Exactly one of `output` and `error` is
+/// set.
+#[derive(Serialize)]
+pub struct BatchItemResult {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<FormattedOutput>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+impl BatchItemResult {
+    pub fn new(name: String, result: Result<FormattedOutput, ApiError>) -> Self {
+        match result {
+            Ok(output) => BatchItemResult {
+                name,
+                output: Some(output),
+                error: None,
+            },
+            Err(err) => BatchItemResult {
+                name,
+                output: None,
+                error: Some(ErrorBody::from(&err)),
+            },
+        }
+    }
+}
+
+/// Response body of the batch endpoint. Results are in the same order as
+/// the submitted images.
+#[derive(Serialize)]
+pub struct BatchResponse {
+    pub results: Vec<BatchItemResult>,
+
+    /// Lines merged across consecutive images, if requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spans: Option<Vec<TextSpan>>,
+}
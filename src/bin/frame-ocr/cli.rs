@@ -0,0 +1,379 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::anyhow;
+use clap::{Args, Subcommand, ValueEnum};
+use frame_ocr::confidence::parse_min_confidence;
+use frame_ocr::markup::{format_alto_pages, format_hocr_pages, Page};
+use frame_ocr::models::{fetch_model, verify_model};
+use frame_ocr::output::{format_text_output, OcrOutput, OcrResult, OutputFormat};
+use frame_ocr::pipeline::OcrOptions;
+use frame_ocr::preprocess::{parse_steps, PreprocessStep};
+use frame_ocr::roi::{parse_rois, Roi};
+use tracing::error;
+
+use crate::app::{load_models, new_span_tracker, ocr_image, ocr_video, CONFIG};
+use crate::batch::BatchItemResult;
+use crate::error::ApiError;
+
+/// What the binary does.
+#[derive(Clone, Debug, Default, Subcommand)]
+pub enum Command {
+    /// Start the HTTP server. This is the default.
+    #[default]
+    Serve,
+
+    /// OCR image files and print the results to stdout.
+    Ocr(OcrArgs),
+
+    /// OCR the frames of a video file and print the timeline as JSON.
+    Video {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+
+    /// Download or check the model files.
+    Models {
+        #[command(subcommand)]
+        command: ModelsCommand,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ModelsCommand {
+    /// Download the models, if they are not already cached, and verify them.
+    Fetch,
+
+    /// Check that the models are available locally, match their checksums and
+    /// can be loaded. Never downloads anything.
+    Verify,
+}
+
+/// Format of the results printed by the `ocr` command.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum CliFormat {
+    /// Recognized lines of each file.
+    #[default]
+    Text,
+
+    /// One JSON object per file, each on its own line, in the same form as
+    /// the items of a batch response.
+    Json,
+
+    /// One tab-separated row per word, with a header row.
+    Tsv,
+
+    /// One hOCR document with a page for each file.
+    Hocr,
+
+    /// One ALTO XML document with a page for each file, in the given order.
+    Alto,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct OcrArgs {
+    /// Image files or glob patterns. `-`, or no files at all, reads an image
+    /// from stdin.
+    pub files: Vec<String>,
+
+    /// Output format.
+    #[arg(long, short, value_enum, default_value_t)]
+    pub format: CliFormat,
+
+    /// Drop lines whose average character confidence is below this value.
+    #[arg(long, value_parser = parse_min_confidence)]
+    pub min_confidence: Option<f32>,
+
+    /// Restrict OCR to these regions, eg. `subtitles:0%,80%,100%,20%`.
+    #[arg(long, value_parser = parse_rois)]
+    pub roi: Option<::std::vec::Vec<Roi>>,
+
+    /// Preprocessing applied before OCR, eg. `scale:2,grayscale,invert`.
+    #[arg(long, value_parser = parse_steps)]
+    pub preprocess: Option<::std::vec::Vec<PreprocessStep>>,
+
+    /// Detect and correct rotated or skewed text.
+    #[arg(long)]
+    pub auto_rotate: bool,
+}
+
+/// An image to process from the command line.
+pub enum Input {
+    Stdin,
+    File(String),
+}
+
+impl Input {
+    /// Name used for the input in the output.
+    pub fn name(&self) -> &str {
+        match self {
+            Input::Stdin => "-",
+            Input::File(path) => path,
+        }
+    }
+
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Input::Stdin => {
+                let mut data = Vec::new();
+                io::stdin().lock().read_to_end(&mut data)?;
+                Ok(data)
+            }
+            Input::File(path) => fs::read(path),
+        }
+    }
+}
+
+/// Expand the file arguments of the `ocr` command into a list of inputs.
+///
+/// Arguments that contain glob characters are expanded, and must match at
+/// least one file. Other arguments are used as-is.
+pub fn expand_inputs(files: &[String]) -> Result<Vec<Input>, anyhow::Error> {
+    if files.is_empty() {
+        return Ok(vec![Input::Stdin]);
+    }
+    let mut inputs = Vec::new();
+    for file in files {
+        if file == "-" {
+            inputs.push(Input::Stdin);
+        } else if file.contains(['*', '?', '[']) {
+            let mut matched = false;
+            for path in glob::glob(file)? {
+                inputs.push(Input::File(path?.display().to_string()));
+                matched = true;
+            }
+            if !matched {
+                return Err(anyhow!("No files match {}", file));
+            }
+        } else {
+            inputs.push(Input::File(file.clone()));
+        }
+    }
+    Ok(inputs)
+}
+
+/// Header row of the TSV output.
+pub const TSV_HEADER: &str = "file\tline\tword\tleft\ttop\twidth\theight\tconfidence\ttext";
+
+/// Write one TSV row for each word of `output`.
+pub fn write_tsv(out: &mut impl Write, name: &str, output: &OcrOutput) -> io::Result<()> {
+    // Tabs and newlines in the file name would break the row.
+    let name = name.replace(['\t', '\n'], " ");
+    for (line_index, line) in output.lines.iter().enumerate() {
+        for (word_index, word) in line.words.iter().enumerate() {
+            let bbox = word.geometry.bounding_box;
+            let confidence = word
+                .confidence
+                .map(|c| format!("{:.3}", c))
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                name,
+                line_index,
+                word_index,
+                bbox.x,
+                bbox.y,
+                bbox.width,
+                bbox.height,
+                confidence,
+                word.text
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// OCR a video file given on the command line and print the timeline to
+/// stdout.
+pub fn run_video_cli(path: &Path) -> ExitCode {
+    if let Err(err) = load_models() {
+        error!("Failed to load models: {:#}", err);
+        return ExitCode::FAILURE;
+    }
+
+    let opts = OcrOptions::default();
+    let spans = CONFIG.dedupe.then(new_span_tracker);
+    match ocr_video(
+        path,
+        CONFIG.frame_sampling(),
+        OutputFormat::Text,
+        None,
+        &opts,
+        spans,
+    ) {
+        Ok(response) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&response).expect("Failed to serialize output")
+            );
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            error!(path = %path.display(), "{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Write the result for one input of the `ocr` command to `out`.
+fn write_cli_result(
+    out: &mut impl Write,
+    args: &OcrArgs,
+    name: &str,
+    show_name: bool,
+    result: Result<OcrResult, ApiError>,
+) -> io::Result<()> {
+    match (args.format, result) {
+        (CliFormat::Json, result) => {
+            let item = BatchItemResult::new(
+                name.to_string(),
+                result.map(|result| result.format(OutputFormat::Json)),
+            );
+            serde_json::to_writer(&mut *out, &item)?;
+            writeln!(out)
+        }
+        (CliFormat::Text, Ok(result)) => {
+            if show_name {
+                writeln!(out, "==> {} <==", name)?;
+            }
+            writeln!(out, "{}", format_text_output(&result.lines))
+        }
+        (CliFormat::Tsv, Ok(result)) => {
+            write_tsv(out, name, &OcrOutput::new(&result.page, &result.lines))
+        }
+        // Errors are only logged in these formats.
+        (CliFormat::Text | CliFormat::Tsv, Err(_)) => Ok(()),
+        // Pages are collected and written as one document at the end.
+        (CliFormat::Hocr | CliFormat::Alto, _) => Ok(()),
+    }
+}
+
+/// Write the hOCR or ALTO document for the inputs of the `ocr` command that
+/// were processed.
+fn write_cli_document(
+    out: &mut impl Write,
+    format: CliFormat,
+    pages: &[(&str, OcrOutput)],
+) -> io::Result<()> {
+    let pages: Vec<Page> = pages
+        .iter()
+        .map(|(name, output)| Page {
+            image: Some(name),
+            output,
+        })
+        .collect();
+    match format {
+        CliFormat::Hocr => write!(out, "{}", format_hocr_pages(&pages)),
+        CliFormat::Alto => write!(out, "{}", format_alto_pages(&pages)),
+        CliFormat::Text | CliFormat::Json | CliFormat::Tsv => Ok(()),
+    }
+}
+
+/// OCR the images given to the `ocr` command and print the results to
+/// stdout.
+///
+/// Every input is processed even if some fail, but the exit code reports the
+/// failure.
+pub fn run_ocr_cli(args: &OcrArgs) -> ExitCode {
+    let inputs = match expand_inputs(&args.files) {
+        Ok(inputs) => inputs,
+        Err(err) => {
+            error!("{:#}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(err) = load_models() {
+        error!("Failed to load models: {:#}", err);
+        return ExitCode::FAILURE;
+    }
+
+    let opts = OcrOptions {
+        decode_method: None,
+        score_confidence: args.format != CliFormat::Text,
+        min_confidence: args.min_confidence,
+        preprocess: args.preprocess.clone().unwrap_or_default(),
+        auto_rotate: args.auto_rotate,
+    };
+    let mut out = io::stdout().lock();
+    let mut failed = false;
+    if args.format == CliFormat::Tsv {
+        if let Err(err) = writeln!(out, "{}", TSV_HEADER) {
+            error!("Failed to write output: {}", err);
+            return ExitCode::FAILURE;
+        }
+    }
+    let mut pages = Vec::new();
+    for input in &inputs {
+        let result = input
+            .read()
+            .map_err(|err| {
+                ApiError::InvalidRequest(format!("Failed to read {}: {}", input.name(), err))
+            })
+            .and_then(|data| ocr_image(&data, None, args.roi.as_deref(), &opts));
+        match &result {
+            Ok(result) if matches!(args.format, CliFormat::Hocr | CliFormat::Alto) => {
+                pages.push((input.name(), OcrOutput::new(&result.page, &result.lines)));
+            }
+            Ok(_) => {}
+            Err(err) => {
+                error!(file = input.name(), "{}", err);
+                failed = true;
+            }
+        }
+        if let Err(err) = write_cli_result(&mut out, args, input.name(), inputs.len() > 1, result) {
+            error!("Failed to write output: {}", err);
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Err(err) = write_cli_document(&mut out, args.format, &pages) {
+        error!("Failed to write output: {}", err);
+        return ExitCode::FAILURE;
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Run a `models` command and print the path and SHA-256 digest of each
+/// model to stdout.
+pub fn run_models_cli(command: &ModelsCommand) -> ExitCode {
+    let models = [
+        (
+            "detection",
+            CONFIG.detection_model_source(),
+            CONFIG.detection_model_sha256.as_deref(),
+        ),
+        (
+            "recognition",
+            CONFIG.recognition_model_source(),
+            CONFIG.recognition_model_sha256.as_deref(),
+        ),
+    ];
+    let mut failed = false;
+    for (name, source, expected_sha256) in models {
+        let result = match command {
+            ModelsCommand::Fetch => fetch_model(source, expected_sha256)
+                .and_then(|_| verify_model(source, expected_sha256)),
+            ModelsCommand::Verify => verify_model(source, expected_sha256),
+        };
+        match result {
+            Ok((path, digest)) => println!("{}\t{}\t{}", name, path.display(), digest),
+            Err(err) => {
+                error!(model = name, "{:#}", err);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,525 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use clap::Parser;
+use frame_ocr::engine::{DecodeMode, EngineConfig};
+use frame_ocr::models::{ModelSource, DETECTION_MODEL, RECOGNITION_MODEL};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Command;
+use crate::logging::{parse_filter, LogFormat};
+use crate::result_cache::ResultCacheLimits;
+use crate::telemetry::traces_url;
+use crate::video::FrameSampling;
+
+/// File names of the models inside `--model-dir`.
+const DETECTION_MODEL_FILE: &str = "text-detection.rten";
+const RECOGNITION_MODEL_FILE: &str = "text-recognition.rten";
+
+/// Command-line flags and environment variables.
+///
+/// Options that are not set here fall back to the configuration file, if
+/// any, and then to the defaults in [Config]. They can be given before or
+/// after the subcommand.
+#[derive(Clone, Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// TOML configuration file. Options set on the command line or in the
+    /// environment take precedence over the file.
+    #[arg(long, global = true, env = "FRAME_OCR_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address to listen on. Can be repeated to listen on several addresses.
+    #[arg(long, global = true, env = "FRAME_OCR_LISTEN", value_delimiter = ',')]
+    listen: Option<Vec<String>>,
+
+    /// Port to listen on.
+    #[arg(long, global = true, env = "FRAME_OCR_PORT")]
+    port: Option<u16>,
+
+    /// Number of HTTP worker threads. Defaults to the number of CPU cores.
+    #[arg(long, global = true, env = "FRAME_OCR_WORKERS")]
+    workers: Option<usize>,
+
+    /// Keep-alive timeout for idle connections, in seconds. 0 disables
+    /// keep-alive.
+    #[arg(long, global = true, env = "FRAME_OCR_KEEP_ALIVE")]
+    keep_alive: Option<u64>,
+
+    /// Maximum size of a request body, in bytes.
+    #[arg(long, global = true, env = "FRAME_OCR_MAX_PAYLOAD_SIZE")]
+    max_payload_size: Option<usize>,
+
+    /// Number of threads that run OCR inference.
+    #[arg(long, global = true, env = "FRAME_OCR_COMPUTE_THREADS")]
+    compute_threads: Option<usize>,
+
+    /// Number of requests that can wait for a compute thread before new
+    /// requests are rejected with 503.
+    #[arg(long, global = true, env = "FRAME_OCR_QUEUE_SIZE")]
+    queue_size: Option<usize>,
+
+    /// Value of the `Retry-After` header, in seconds, sent when the queue is
+    /// full.
+    #[arg(long, global = true, env = "FRAME_OCR_RETRY_AFTER")]
+    retry_after: Option<u64>,
+
+    /// Decode method used when a request does not specify one.
+    #[arg(long, global = true, env = "FRAME_OCR_DECODE_METHOD")]
+    decode_method: Option<DecodeMode>,
+
+    /// Beam width used for beam search decoding.
+    #[arg(long, global = true, env = "FRAME_OCR_BEAM_WIDTH")]
+    beam_width: Option<u32>,
+
+    /// Directory containing `text-detection.rten` and `text-recognition.rten`.
+    ///
+    /// When set, models are loaded from this directory and never downloaded.
+    #[arg(long, global = true, env = "FRAME_OCR_MODEL_DIR")]
+    model_dir: Option<PathBuf>,
+
+    /// Path of the text detection model. Overrides `--model-dir`.
+    #[arg(long, global = true, env = "FRAME_OCR_DETECTION_MODEL")]
+    detection_model: Option<PathBuf>,
+
+    /// Path of the text recognition model. Overrides `--model-dir`.
+    #[arg(long, global = true, env = "FRAME_OCR_RECOGNITION_MODEL")]
+    recognition_model: Option<PathBuf>,
+
+    /// Expected SHA-256 digest (hex) of the text detection model.
+    #[arg(long, global = true, env = "FRAME_OCR_DETECTION_MODEL_SHA256")]
+    detection_model_sha256: Option<String>,
+
+    /// Expected SHA-256 digest (hex) of the text recognition model.
+    #[arg(long, global = true, env = "FRAME_OCR_RECOGNITION_MODEL_SHA256")]
+    recognition_model_sha256: Option<String>,
+
+    /// ffmpeg binary used to decode videos.
+    #[arg(long, global = true, env = "FRAME_OCR_FFMPEG")]
+    ffmpeg: Option<PathBuf>,
+
+    /// Interval between frames sampled from videos, in seconds.
+    #[arg(long, global = true, env = "FRAME_OCR_FRAME_INTERVAL")]
+    frame_interval: Option<f64>,
+
+    /// Sample video frames on scene changes whose score (0 to 1) exceeds this
+    /// threshold, instead of at a fixed interval.
+    #[arg(long, global = true, env = "FRAME_OCR_SCENE_THRESHOLD")]
+    scene_threshold: Option<f64>,
+
+    /// Maximum number of frames sampled from a video. Longer videos are
+    /// rejected.
+    #[arg(long, global = true, env = "FRAME_OCR_MAX_VIDEO_FRAMES")]
+    max_video_frames: Option<usize>,
+
+    /// Merge lines repeated across consecutive frames of a batch or video
+    /// into spans, unless a request says otherwise.
+    #[arg(long, global = true, env = "FRAME_OCR_DEDUPE", num_args = 0..=1, default_missing_value = "true")]
+    dedupe: Option<bool>,
+
+    /// Minimum text similarity (0 to 1) for a line to continue a span from
+    /// the previous frame.
+    #[arg(long, global = true, env = "FRAME_OCR_DEDUPE_SIMILARITY")]
+    dedupe_similarity: Option<f32>,
+
+    /// Minimum overlap (0 to 1) of a line's bounding box with the previous
+    /// frame's for the line to continue a span.
+    #[arg(long, global = true, env = "FRAME_OCR_DEDUPE_OVERLAP")]
+    dedupe_overlap: Option<f32>,
+
+    /// Number of recently processed frames kept to skip OCR of similar
+    /// frames. 0 disables the cache.
+    #[arg(long, global = true, env = "FRAME_OCR_FRAME_CACHE_SIZE")]
+    frame_cache_size: Option<usize>,
+
+    /// Maximum number of differing bits (out of 256) between the perceptual
+    /// hashes of two frames for them to be considered the same.
+    #[arg(long, global = true, env = "FRAME_OCR_FRAME_CACHE_DISTANCE")]
+    frame_cache_distance: Option<u32>,
+
+    /// Maximum number of `/process` responses kept in the result cache. 0
+    /// disables the cache.
+    #[arg(long, global = true, env = "FRAME_OCR_RESULT_CACHE_ENTRIES")]
+    result_cache_entries: Option<usize>,
+
+    /// Maximum total size of the cached responses kept in memory, in bytes.
+    #[arg(long, global = true, env = "FRAME_OCR_RESULT_CACHE_MAX_BYTES")]
+    result_cache_max_bytes: Option<usize>,
+
+    /// Time after which cached responses expire, in seconds. 0 keeps them
+    /// until they are evicted.
+    #[arg(long, global = true, env = "FRAME_OCR_RESULT_CACHE_TTL")]
+    result_cache_ttl: Option<u64>,
+
+    /// Directory that cached responses evicted from memory are moved to.
+    #[arg(long, global = true, env = "FRAME_OCR_RESULT_CACHE_DIR")]
+    result_cache_dir: Option<PathBuf>,
+
+    /// Maximum total size of the cached responses in
+    /// `--result-cache-dir`, in bytes.
+    #[arg(long, global = true, env = "FRAME_OCR_RESULT_CACHE_DIR_MAX_BYTES")]
+    result_cache_dir_max_bytes: Option<u64>,
+
+    /// Format of log lines.
+    #[arg(long, global = true, env = "FRAME_OCR_LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+
+    /// Log level, or comma-separated `target=level` directives, eg.
+    /// `info,frame_ocr=debug`.
+    #[arg(long, global = true, env = "FRAME_OCR_LOG_LEVEL")]
+    log_level: Option<String>,
+
+    /// URL of an OpenTelemetry collector to export traces to over OTLP/HTTP,
+    /// eg. `http://localhost:4318`.
+    #[arg(long, global = true, env = "FRAME_OCR_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+}
+
+/// Service configuration.
+///
+/// Options are taken from command-line flags, then `FRAME_OCR_*` environment
+/// variables, then the TOML file given by `--config`, and finally the
+/// defaults. Keys in the file use the same names as the fields below.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Addresses to listen on.
+    pub listen: Vec<String>,
+
+    /// Port to listen on.
+    pub port: u16,
+
+    /// Number of HTTP worker threads, or `None` to use the number of CPU
+    /// cores.
+    pub workers: Option<usize>,
+
+    /// Keep-alive timeout for idle connections, in seconds.
+    pub keep_alive: u64,
+
+    /// Maximum size of a request body, in bytes.
+    pub max_payload_size: usize,
+
+    /// Number of threads that run OCR inference.
+    ///
+    /// Inference is itself multi-threaded, so a small number is usually
+    /// enough to keep all cores busy.
+    pub compute_threads: usize,
+
+    /// Number of requests that can wait for a compute thread.
+    pub queue_size: usize,
+
+    /// `Retry-After` value, in seconds, sent when the queue is full.
+    pub retry_after: u64,
+
+    /// Decode method used when a request does not specify one.
+    pub decode_method: DecodeMode,
+
+    /// Beam width used for beam search decoding.
+    pub beam_width: u32,
+
+    /// Directory containing the model files.
+    pub model_dir: Option<PathBuf>,
+
+    /// Path of the text detection model.
+    pub detection_model: Option<PathBuf>,
+
+    /// Path of the text recognition model.
+    pub recognition_model: Option<PathBuf>,
+
+    /// Expected SHA-256 digest of the text detection model.
+    pub detection_model_sha256: Option<String>,
+
+    /// Expected SHA-256 digest of the text recognition model.
+    pub recognition_model_sha256: Option<String>,
+
+    /// ffmpeg binary used to decode videos.
+    pub ffmpeg: PathBuf,
+
+    /// Interval between frames sampled from videos, in seconds.
+    pub frame_interval: f64,
+
+    /// Scene change threshold for sampling video frames. When set, frames
+    /// are sampled on scene changes instead of at `frame_interval`.
+    pub scene_threshold: Option<f64>,
+
+    /// Maximum number of frames sampled from a video. Processing fails once
+    /// a video has more.
+    pub max_video_frames: usize,
+
+    /// Merge lines repeated across consecutive frames into spans by default.
+    pub dedupe: bool,
+
+    /// Minimum edit-distance similarity of two lines' text for the later one
+    /// to continue a span.
+    pub dedupe_similarity: f32,
+
+    /// Minimum overlap of two lines' bounding boxes, as a fraction of the
+    /// smaller box, for the later one to continue a span.
+    pub dedupe_overlap: f32,
+
+    /// Number of recently processed frames whose results are reused for
+    /// similar frames. 0 disables the cache.
+    pub frame_cache_size: usize,
+
+    /// Maximum Hamming distance between the perceptual hashes of two frames
+    /// for the cached result of one to be used for the other.
+    pub frame_cache_distance: u32,
+
+    /// Maximum number of `/process` responses kept in memory. 0 disables the
+    /// result cache.
+    pub result_cache_entries: usize,
+
+    /// Maximum total size of the responses kept in memory, in bytes.
+    pub result_cache_max_bytes: usize,
+
+    /// Time after which cached responses expire, in seconds, or 0 to keep
+    /// them until they are evicted.
+    pub result_cache_ttl: u64,
+
+    /// Directory that responses evicted from memory are moved to.
+    pub result_cache_dir: Option<PathBuf>,
+
+    /// Maximum total size of the responses in `result_cache_dir`, in bytes.
+    pub result_cache_dir_max_bytes: u64,
+
+    /// Format of log lines written to stderr.
+    pub log_format: LogFormat,
+
+    /// Log level or filter directives.
+    pub log_level: String,
+
+    /// URL of an OpenTelemetry collector that traces are exported to, or
+    /// `None` to disable export.
+    pub otlp_endpoint: Option<String>,
+
+    /// Subcommand given on the command line.
+    #[serde(skip)]
+    pub command: Command,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec!["0.0.0.0".to_string()],
+            port: 8080,
+            workers: None,
+            keep_alive: 5,
+            max_payload_size: 32 * 1024 * 1024,
+            compute_threads: 2,
+            queue_size: 64,
+            retry_after: 1,
+            decode_method: DecodeMode::default(),
+            beam_width: 10,
+            model_dir: None,
+            detection_model: None,
+            recognition_model: None,
+            detection_model_sha256: None,
+            recognition_model_sha256: None,
+            ffmpeg: PathBuf::from("ffmpeg"),
+            frame_interval: 1.0,
+            scene_threshold: None,
+            max_video_frames: 1000,
+            dedupe: false,
+            dedupe_similarity: 0.8,
+            dedupe_overlap: 0.5,
+            frame_cache_size: 0,
+            frame_cache_distance: 4,
+            result_cache_entries: 256,
+            result_cache_max_bytes: 64 * 1024 * 1024,
+            result_cache_ttl: 3600,
+            result_cache_dir: None,
+            result_cache_dir_max_bytes: 1024 * 1024 * 1024,
+            log_format: LogFormat::default(),
+            log_level: "info".to_string(),
+            otlp_endpoint: None,
+            command: Command::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from the command line, environment and
+    /// configuration file.
+    pub fn load() -> Result<Config, anyhow::Error> {
+        let args = Args::parse();
+
+        let mut config = match &args.config {
+            Some(path) => {
+                let contents = fs::read_to_string(path).with_context(|| {
+                    format!("Failed to read configuration file {}", path.display())
+                })?;
+                toml::from_str(&contents)
+                    .with_context(|| format!("Invalid configuration file {}", path.display()))?
+            }
+            None => Config::default(),
+        };
+
+        macro_rules! apply_args {
+            ($($field:ident),*) => {
+                $(if let Some(value) = args.$field {
+                    config.$field = value;
+                })*
+            };
+        }
+        apply_args!(
+            listen,
+            port,
+            keep_alive,
+            max_payload_size,
+            compute_threads,
+            queue_size,
+            retry_after,
+            decode_method,
+            beam_width,
+            ffmpeg,
+            frame_interval,
+            max_video_frames,
+            dedupe,
+            dedupe_similarity,
+            dedupe_overlap,
+            frame_cache_size,
+            frame_cache_distance,
+            result_cache_entries,
+            result_cache_max_bytes,
+            result_cache_ttl,
+            result_cache_dir_max_bytes,
+            log_format,
+            log_level
+        );
+
+        macro_rules! apply_optional_args {
+            ($($field:ident),*) => {
+                $(if args.$field.is_some() {
+                    config.$field = args.$field;
+                })*
+            };
+        }
+        apply_optional_args!(
+            workers,
+            model_dir,
+            detection_model,
+            recognition_model,
+            detection_model_sha256,
+            recognition_model_sha256,
+            scene_threshold,
+            result_cache_dir,
+            otlp_endpoint
+        );
+
+        config.command = args.command.unwrap_or_default();
+
+        if let Some(model_dir) = &config.model_dir {
+            config
+                .detection_model
+                .get_or_insert_with(|| model_dir.join(DETECTION_MODEL_FILE));
+            config
+                .recognition_model
+                .get_or_insert_with(|| model_dir.join(RECOGNITION_MODEL_FILE));
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.listen.is_empty() {
+            return Err(anyhow!("At least one listen address is required"));
+        }
+        if self.workers == Some(0) {
+            return Err(anyhow!("workers must be at least 1"));
+        }
+        if self.compute_threads == 0 {
+            return Err(anyhow!("compute_threads must be at least 1"));
+        }
+        if self.queue_size == 0 {
+            return Err(anyhow!("queue_size must be at least 1"));
+        }
+        if self.beam_width == 0 {
+            return Err(anyhow!("beam_width must be at least 1"));
+        }
+        if !(self.frame_interval.is_finite() && self.frame_interval > 0.) {
+            return Err(anyhow!("frame_interval must be greater than 0"));
+        }
+        if self.max_video_frames == 0 {
+            return Err(anyhow!("max_video_frames must be greater than 0"));
+        }
+        if !(0. ..=1.).contains(&self.dedupe_similarity) {
+            return Err(anyhow!("dedupe_similarity must be between 0 and 1"));
+        }
+        if !(0. ..=1.).contains(&self.dedupe_overlap) {
+            return Err(anyhow!("dedupe_overlap must be between 0 and 1"));
+        }
+        if self.frame_cache_distance > 256 {
+            return Err(anyhow!("frame_cache_distance must be at most 256"));
+        }
+        parse_filter(&self.log_level).context("Invalid log_level")?;
+        if let Some(endpoint) = &self.otlp_endpoint {
+            traces_url(endpoint).context("Invalid otlp_endpoint")?;
+        }
+        if let Some(threshold) = self.scene_threshold {
+            if !(0. ..=1.).contains(&threshold) {
+                return Err(anyhow!("scene_threshold must be between 0 and 1"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the `(address, port)` pairs to bind the server to.
+    pub fn bind_addrs(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.listen.iter().map(|addr| (addr.as_str(), self.port))
+    }
+
+    /// Return how frames are sampled from videos when a request does not
+    /// specify it.
+    pub fn frame_sampling(&self) -> FrameSampling {
+        match self.scene_threshold {
+            Some(threshold) => FrameSampling::SceneChange(threshold),
+            None => FrameSampling::Interval(self.frame_interval),
+        }
+    }
+
+    /// Return the limits of the `/process` result cache.
+    pub fn result_cache_limits(&self) -> ResultCacheLimits {
+        ResultCacheLimits {
+            max_entries: self.result_cache_entries,
+            max_bytes: self.result_cache_max_bytes,
+            ttl: (self.result_cache_ttl > 0).then(|| Duration::from_secs(self.result_cache_ttl)),
+            dir: self.result_cache_dir.clone(),
+            dir_max_bytes: self.result_cache_dir_max_bytes,
+        }
+    }
+
+    /// Return the location of the text detection model.
+    pub fn detection_model_source(&self) -> ModelSource<'_> {
+        match &self.detection_model {
+            Some(path) => ModelSource::File(path),
+            None => ModelSource::Url(DETECTION_MODEL),
+        }
+    }
+
+    /// Return the location of the text recognition model.
+    pub fn recognition_model_source(&self) -> ModelSource<'_> {
+        match &self.recognition_model {
+            Some(path) => ModelSource::File(path),
+            None => ModelSource::Url(RECOGNITION_MODEL),
+        }
+    }
+
+    /// Return the models and decoding settings to construct the OCR engines
+    /// with.
+    pub fn engine_config(&self) -> EngineConfig<'_> {
+        EngineConfig {
+            detection_model: self.detection_model_source(),
+            detection_model_sha256: self.detection_model_sha256.as_deref(),
+            recognition_model: self.recognition_model_source(),
+            recognition_model_sha256: self.recognition_model_sha256.as_deref(),
+            decode_method: self.decode_method,
+            beam_width: self.beam_width,
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt;
+
+use actix_web::error::BlockingError;
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use frame_ocr::decode::DecodeError;
+use frame_ocr::pipeline::PipelineError;
+use frame_ocr::service::OcrError;
+use serde::Serialize;
+use tracing::error;
+
+use crate::video::VideoError;
+
+/// Error returned to API clients.
+///
+/// Each variant maps to an HTTP status and a machine-readable `code` that is
+/// returned together with a human-readable `message` in a JSON body.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request parameters are invalid.
+    InvalidRequest(String),
+
+    /// The image data could not be decoded.
+    InvalidImage(String),
+
+    /// The video data could not be decoded.
+    InvalidVideo(String),
+
+    /// The request body exceeds the configured size limit.
+    PayloadTooLarge { limit: usize },
+
+    /// The video has more sampled frames than the configured limit.
+    TooManyFrames { limit: usize },
+
+    /// The image format is not recognized or not supported.
+    UnsupportedMediaType(String),
+
+    /// The image was decoded but cannot be processed, eg. because it is empty.
+    UnprocessableImage(String),
+
+    /// The compute queue is full.
+    QueueFull { retry_after: u64 },
+
+    /// Models are still loading, or failed to load.
+    NotReady { retry_after: u64 },
+
+    /// Processing failed due to a fault in the service.
+    Internal(String),
+}
+
+/// JSON body of an error response.
+#[derive(Serialize)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl From<&ApiError> for ErrorBody {
+    fn from(err: &ApiError) -> Self {
+        ErrorBody {
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl ApiError {
+    /// Return the machine-readable error code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidRequest(_) => "invalid_request",
+            ApiError::InvalidImage(_) => "invalid_image",
+            ApiError::InvalidVideo(_) => "invalid_video",
+            ApiError::PayloadTooLarge { .. } => "payload_too_large",
+            ApiError::TooManyFrames { .. } => "too_many_frames",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::UnprocessableImage(_) => "unprocessable_image",
+            ApiError::QueueFull { .. } => "queue_full",
+            ApiError::NotReady { .. } => "not_ready",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::InvalidRequest(msg)
+            | ApiError::InvalidImage(msg)
+            | ApiError::InvalidVideo(msg)
+            | ApiError::UnsupportedMediaType(msg)
+            | ApiError::UnprocessableImage(msg)
+            | ApiError::Internal(msg) => write!(f, "{}", msg),
+            ApiError::PayloadTooLarge { limit } => write!(
+                f,
+                "Request body exceeds the maximum size of {} bytes",
+                limit
+            ),
+            ApiError::TooManyFrames { limit } => write!(
+                f,
+                "Video has more than the maximum of {} sampled frames",
+                limit
+            ),
+            ApiError::QueueFull { .. } => {
+                write!(f, "Too many requests are queued, try again later")
+            }
+            ApiError::NotReady { .. } => {
+                write!(f, "The service is not ready yet, try again later")
+            }
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidRequest(_) | ApiError::InvalidImage(_) | ApiError::InvalidVideo(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            ApiError::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::UnprocessableImage(_) | ApiError::TooManyFrames { .. } => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ApiError::QueueFull { .. } | ApiError::NotReady { .. } => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::QueueFull { retry_after } | ApiError::NotReady { retry_after } = self {
+            response.insert_header((RETRY_AFTER, retry_after.to_string()));
+        }
+        response.json(ErrorBody::from(self))
+    }
+}
+
+impl From<DecodeError> for ApiError {
+    fn from(err: DecodeError) -> Self {
+        match err {
+            DecodeError::Unsupported(_) | DecodeError::Unrecognized => {
+                ApiError::UnsupportedMediaType(err.to_string())
+            }
+            DecodeError::Invalid(_) => ApiError::InvalidImage(err.to_string()),
+        }
+    }
+}
+
+impl From<PipelineError> for ApiError {
+    fn from(err: PipelineError) -> Self {
+        match err {
+            PipelineError::EmptyImage
+            | PipelineError::InvalidImage(_)
+            | PipelineError::Preprocess(_) => ApiError::UnprocessableImage(err.to_string()),
+            err => {
+                error!(error = ?err, "Request failed");
+                ApiError::Internal(err.to_string())
+            }
+        }
+    }
+}
+
+impl From<OcrError> for ApiError {
+    fn from(err: OcrError) -> Self {
+        match err {
+            OcrError::Decode(err) => err.into(),
+            OcrError::Pipeline(err) => err.into(),
+        }
+    }
+}
+
+impl From<VideoError> for ApiError {
+    fn from(err: VideoError) -> Self {
+        match err {
+            VideoError::Ffmpeg(_) => ApiError::InvalidVideo(err.to_string()),
+            VideoError::TooManyFrames(limit) => ApiError::TooManyFrames { limit },
+            err => {
+                error!(error = ?err, "Request failed");
+                ApiError::Internal(err.to_string())
+            }
+        }
+    }
+}
+
+impl From<BlockingError> for ApiError {
+    fn from(err: BlockingError) -> Self {
+        error!(error = %err, "Blocking task failed");
+        ApiError::Internal(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+    use frame_ocr::decode::{decode_image, DecodeError};
+
+    use super::ApiError;
+
+    fn decode_error(data: &[u8]) -> ApiError {
+        decode_image(data, None).err().unwrap().into()
+    }
+
+    #[test]
+    fn test_decode_error_status() {
+        let err = ApiError::from(DecodeError::Unrecognized);
+        assert_eq!(err.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(err.code(), "unsupported_media_type");
+
+        let err = decode_error(b"qoif\0\0\0\x04\0\0\0\x03\x03\0");
+        assert_eq!(err.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        // A PNG signature followed by garbage.
+        let err = decode_error(b"\x89PNG\r\n\x1a\ngarbage");
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(err.code(), "invalid_image");
+    }
+
+    #[test]
+    fn test_error_response() {
+        let response = ApiError::QueueFull { retry_after: 3 }.error_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers().get("retry-after").unwrap(), "3");
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::RwLock;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Whether the service is able to process requests.
+#[derive(Clone, Debug, Default)]
+pub enum ReadyState {
+    /// Models are being fetched and loaded.
+    #[default]
+    Loading,
+
+    /// Models are loaded and passed the self-test.
+    Ready {
+        /// Time taken to load the models and run the self-test.
+        load_time: Duration,
+    },
+
+    /// Loading the models or the self-test failed.
+    Failed(String),
+}
+
+/// JSON body of a `/readyz` response.
+#[derive(Serialize)]
+pub struct ReadyReport {
+    pub status: &'static str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_load_seconds: Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Readiness of the service, updated as models are loaded in the background.
+#[derive(Default)]
+pub struct Readiness {
+    state: RwLock<ReadyState>,
+}
+
+impl Readiness {
+    pub fn set(&self, state: ReadyState) {
+        *self.state.write().unwrap_or_else(|err| err.into_inner()) = state;
+    }
+
+    pub fn state(&self) -> ReadyState {
+        self.state
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(
+            *self.state.read().unwrap_or_else(|err| err.into_inner()),
+            ReadyState::Ready { .. }
+        )
+    }
+
+    pub fn report(&self) -> ReadyReport {
+        match self.state() {
+            ReadyState::Loading => ReadyReport {
+                status: "loading",
+                model_load_seconds: None,
+                error: None,
+            },
+            ReadyState::Ready { load_time } => ReadyReport {
+                status: "ready",
+                model_load_seconds: Some(load_time.as_secs_f64()),
+                error: None,
+            },
+            ReadyState::Failed(error) => ReadyReport {
+                status: "failed",
+                model_load_seconds: None,
+                error: Some(error),
+            },
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use clap::ValueEnum;
+use opentelemetry_sdk::trace::Tracer;
+use serde::{Deserialize, Serialize};
+use tracing::field::Empty;
+use tracing::{info, info_span, Instrument};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+use crate::metrics::stage_layer;
+use crate::telemetry::set_remote_parent;
+
+/// Header that carries the ID of a request.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest request ID accepted from a client.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Format of log lines written to stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+
+    /// One JSON object per line.
+    Json,
+
+    /// `key=value` pairs.
+    Logfmt,
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+            LogFormat::Logfmt => write!(f, "logfmt"),
+        }
+    }
+}
+
+/// Parse a log level or a list of `target=level` directives, eg.
+/// `info,frame_ocr=debug`.
+pub fn parse_filter(level: &str) -> Result<EnvFilter, anyhow::Error> {
+    Ok(EnvFilter::try_new(level)?)
+}
+
+/// Install the global logger.
+///
+/// If `tracer` is given, spans are also exported with it. Export, and the
+/// recording of pipeline stage durations, are not affected by `level`.
+pub fn init(format: LogFormat, level: &str, tracer: Option<Tracer>) -> Result<(), anyhow::Error> {
+    let log_layer = match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_ansi(io::stderr().is_terminal())
+            .with_writer(io::stderr)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(io::stderr)
+            .boxed(),
+        LogFormat::Logfmt => tracing_logfmt::builder()
+            .layer()
+            .with_writer(io::stderr)
+            .boxed(),
+    };
+    let trace_layer = tracer.map(|tracer| {
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(LevelFilter::INFO)
+    });
+    tracing_subscriber::registry()
+        .with(log_layer.with_filter(parse_filter(level)?))
+        .with(trace_layer)
+        .with(stage_layer())
+        .try_init()?;
+    Ok(())
+}
+
+/// Return the request ID sent by the client, if it is usable, or a new one.
+fn request_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Middleware that runs each request in a span tagged with its ID, echoes the
+/// ID in the `X-Request-Id` response header and logs the outcome.
+pub async fn request_context(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let start = Instant::now();
+    let id = request_id(&req);
+    let route = req.match_pattern();
+    let span = info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        path = %req.path(),
+        otel.name = %format!("{} {}", req.method(), route.as_deref().unwrap_or(req.path())),
+        otel.kind = "server",
+        http.response.status_code = Empty,
+    );
+    set_remote_parent(&span, req.headers());
+
+    let mut response = next.call(req).instrument(span.clone()).await?;
+    span.record("http.response.status_code", response.status().as_u16());
+    span.in_scope(|| {
+        info!(
+            status = response.status().as_u16(),
+            duration_ms = start.elapsed().as_secs_f64() * 1000.,
+            "Request finished"
+        )
+    });
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(response)
+}
@@ -0,0 +1,58 @@
+use std::error::Error;
+use std::process::ExitCode;
+
+use lazy_static::initialize;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::warn;
+
+#[macro_use]
+extern crate lazy_static;
+
+mod app;
+mod batch;
+mod cli;
+mod config;
+mod error;
+mod health;
+mod logging;
+mod metrics;
+mod pool;
+mod result_cache;
+mod server;
+mod telemetry;
+mod video;
+
+use app::CONFIG;
+use cli::Command;
+
+/// Export any spans that have not been sent to the collector yet.
+fn shutdown_tracer(provider: Option<SdkTracerProvider>) {
+    if let Some(Err(err)) = provider.map(|provider| provider.shutdown()) {
+        warn!("Failed to export traces: {}", err);
+    }
+}
+
+#[actix_web::main]
+async fn main() -> std::result::Result<ExitCode, Box<dyn Error>> {
+    initialize(&CONFIG);
+    let (tracer_provider, tracer) = match &CONFIG.otlp_endpoint {
+        Some(endpoint) => {
+            let (provider, tracer) = telemetry::init_tracer(endpoint)?;
+            (Some(provider), Some(tracer))
+        }
+        None => (None, None),
+    };
+    logging::init(CONFIG.log_format, &CONFIG.log_level, tracer)?;
+
+    let exit_code = match &CONFIG.command {
+        Command::Serve => {
+            server::serve().await?;
+            ExitCode::SUCCESS
+        }
+        Command::Ocr(args) => cli::run_ocr_cli(args),
+        Command::Video { file } => cli::run_video_cli(file),
+        Command::Models { command } => cli::run_models_cli(command),
+    };
+    shutdown_tracer(tracer_provider);
+    Ok(exit_code)
+}
@@ -0,0 +1,202 @@
+use std::time::{Duration, Instant};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use frame_ocr::output::OcrResult;
+use frame_ocr::timing::STAGE_SPAN;
+use prometheus::{
+    exponential_buckets, register_gauge, register_histogram, register_histogram_vec,
+    register_int_counter_vec, register_int_gauge, Encoder, Gauge, Histogram, HistogramVec,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::pool::PoolStats;
+
+lazy_static! {
+    static ref REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "frame_ocr_http_requests_total",
+        "HTTP requests handled, by endpoint and response status.",
+        &["endpoint", "status"]
+    )
+    .unwrap();
+    static ref REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "frame_ocr_http_request_duration_seconds",
+        "Time taken to handle HTTP requests, by endpoint.",
+        &["endpoint"],
+        exponential_buckets(0.005, 2., 14).unwrap()
+    )
+    .unwrap();
+    static ref STAGE_DURATION: HistogramVec = register_histogram_vec!(
+        "frame_ocr_stage_duration_seconds",
+        "Time taken by each stage of processing an image.",
+        &["stage"],
+        exponential_buckets(0.001, 2., 14).unwrap()
+    )
+    .unwrap();
+    static ref IMAGE_WIDTH: Histogram = register_histogram!(
+        "frame_ocr_image_width_pixels",
+        "Width of processed images.",
+        exponential_buckets(128., 2., 7).unwrap()
+    )
+    .unwrap();
+    static ref IMAGE_HEIGHT: Histogram = register_histogram!(
+        "frame_ocr_image_height_pixels",
+        "Height of processed images.",
+        exponential_buckets(128., 2., 7).unwrap()
+    )
+    .unwrap();
+    static ref LINES: Histogram = register_histogram!(
+        "frame_ocr_lines_detected",
+        "Number of text lines found per image.",
+        exponential_buckets(1., 2., 10).unwrap()
+    )
+    .unwrap();
+    static ref WORDS: Histogram = register_histogram!(
+        "frame_ocr_words_detected",
+        "Number of words found per image.",
+        exponential_buckets(1., 2., 12).unwrap()
+    )
+    .unwrap();
+    static ref QUEUED: IntGauge = register_int_gauge!(
+        "frame_ocr_queue_depth",
+        "Jobs waiting for a compute thread."
+    )
+    .unwrap();
+    static ref RUNNING: IntGauge = register_int_gauge!(
+        "frame_ocr_queue_running",
+        "Jobs being run by compute threads."
+    )
+    .unwrap();
+    static ref MODEL_LOAD: Gauge = register_gauge!(
+        "frame_ocr_model_load_seconds",
+        "Time taken to load the models and run the self-test."
+    )
+    .unwrap();
+}
+
+/// Record a handled HTTP request. `endpoint` is the matched route pattern.
+pub fn observe_request(endpoint: &str, status: u16, duration: Duration) {
+    REQUESTS
+        .with_label_values(&[endpoint, &status.to_string()])
+        .inc();
+    REQUEST_DURATION
+        .with_label_values(&[endpoint])
+        .observe(duration.as_secs_f64());
+}
+
+/// Middleware that records the status and duration of every request.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let start = Instant::now();
+    let endpoint = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let response = next.call(req).await?;
+    observe_request(&endpoint, response.status().as_u16(), start.elapsed());
+    Ok(response)
+}
+
+/// Record the size of an image and the amount of text found in it.
+pub fn observe_image(result: &OcrResult) {
+    let words: usize = result
+        .lines
+        .iter()
+        .map(|line| line.line.words().count())
+        .sum();
+    IMAGE_WIDTH.observe(result.page.width as f64);
+    IMAGE_HEIGHT.observe(result.page.height as f64);
+    LINES.observe(result.lines.len() as f64);
+    WORDS.observe(words as f64);
+}
+
+/// A pipeline stage that is running, stored in the extensions of its span.
+struct RunningStage {
+    stage: String,
+    start: Instant,
+}
+
+/// Reads the `stage` field of a stage span.
+#[derive(Default)]
+struct StageVisitor(Option<String>);
+
+impl Visit for StageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "stage" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "stage" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// Layer that records the duration of the pipeline stage spans emitted by
+/// [frame_ocr::timing::time_stage].
+struct StageLayer;
+
+/// Return a layer that records the duration of pipeline stages.
+pub fn stage_layer<S>() -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    StageLayer.with_filter(filter_fn(|meta| {
+        meta.is_span() && meta.name() == STAGE_SPAN
+    }))
+}
+
+impl<S> Layer<S> for StageLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = StageVisitor::default();
+        attrs.record(&mut visitor);
+        if let (Some(stage), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(RunningStage {
+                stage,
+                start: Instant::now(),
+            });
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let extensions = span.extensions();
+        if let Some(running) = extensions.get::<RunningStage>() {
+            STAGE_DURATION
+                .with_label_values(&[&running.stage])
+                .observe(running.start.elapsed().as_secs_f64());
+        }
+    }
+}
+
+pub fn set_model_load_time(duration: Duration) {
+    MODEL_LOAD.set(duration.as_secs_f64());
+}
+
+/// Encode all metrics in the Prometheus text format.
+pub fn render(pool: PoolStats) -> String {
+    QUEUED.set(pool.queued as i64);
+    RUNNING.set(pool.running as i64);
+
+    let mut buf = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buf)
+        .expect("Failed to encode metrics");
+    String::from_utf8(buf).expect("Metrics should be UTF-8")
+}
@@ -0,0 +1,132 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use futures::channel::oneshot;
+use serde::Serialize;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Error returned when a job is submitted to a [ComputePool] whose queue is
+/// full.
+#[derive(Debug)]
+pub struct QueueFull;
+
+/// Snapshot of the load on a [ComputePool].
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct PoolStats {
+    /// Jobs waiting for a worker thread.
+    pub queued: usize,
+
+    /// Jobs currently being run.
+    pub running: usize,
+
+    /// Number of worker threads.
+    pub threads: usize,
+
+    /// Maximum number of jobs that can wait in the queue.
+    pub queue_capacity: usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    queued: AtomicUsize,
+    running: AtomicUsize,
+}
+
+/// Fixed-size pool of threads for running CPU-bound OCR work outside of the
+/// async executor.
+///
+/// Jobs wait in a bounded queue. When the queue is full, new jobs are
+/// rejected instead of piling up, so callers can shed load.
+pub struct ComputePool {
+    sender: SyncSender<Job>,
+    counters: Arc<Counters>,
+    threads: usize,
+    queue_capacity: usize,
+}
+
+impl ComputePool {
+    /// Start a pool with `threads` worker threads and room for
+    /// `queue_capacity` waiting jobs.
+    pub fn new(threads: usize, queue_capacity: usize) -> ComputePool {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue_capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let counters = Arc::new(Counters::default());
+
+        for i in 0..threads {
+            let receiver = receiver.clone();
+            let counters = counters.clone();
+            thread::Builder::new()
+                .name(format!("ocr-worker-{}", i))
+                .spawn(move || worker_loop(&receiver, &counters))
+                .expect("Failed to spawn compute thread");
+        }
+
+        ComputePool {
+            sender,
+            counters,
+            threads,
+            queue_capacity,
+        }
+    }
+
+    /// Queue `f` to run on a worker thread and return a receiver for its
+    /// result.
+    ///
+    /// The receiver is cancelled if `f` panics.
+    pub fn spawn<T, F>(&self, f: F) -> Result<oneshot::Receiver<T>, QueueFull>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            // The caller may have gone away, in which case the result is
+            // discarded.
+            let _ = tx.send(f());
+        });
+
+        self.counters.queued.fetch_add(1, Ordering::SeqCst);
+        match self.sender.try_send(job) {
+            Ok(()) => Ok(rx),
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.counters.queued.fetch_sub(1, Ordering::SeqCst);
+                Err(QueueFull)
+            }
+        }
+    }
+
+    /// Return the current queue depth and number of running jobs.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            queued: self.counters.queued.load(Ordering::SeqCst),
+            running: self.counters.running.load(Ordering::SeqCst),
+            threads: self.threads,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+}
+
+fn worker_loop(receiver: &Mutex<Receiver<Job>>, counters: &Counters) {
+    loop {
+        let job = {
+            let receiver = receiver.lock().unwrap_or_else(|err| err.into_inner());
+            match receiver.recv() {
+                Ok(job) => job,
+                // The pool was dropped.
+                Err(_) => return,
+            }
+        };
+        counters.queued.fetch_sub(1, Ordering::SeqCst);
+        counters.running.fetch_add(1, Ordering::SeqCst);
+
+        // Keep the worker alive if a job panics. The panic message is printed
+        // by the default hook, and the job's result channel is dropped.
+        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+
+        counters.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
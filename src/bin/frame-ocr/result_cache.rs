@@ -0,0 +1,611 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use frame_ocr::models::write_atomic;
+use frame_ocr::output::OutputFormat;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+/// A cached `/process` response body.
+#[derive(Clone)]
+pub struct CachedResponse {
+    pub format: OutputFormat,
+    pub body: Vec<u8>,
+}
+
+impl CachedResponse {
+    /// Encode the response for storage on disk.
+    fn to_file_contents(&self) -> Vec<u8> {
+        let format = match self.format {
+            OutputFormat::Text => b"text\n".as_slice(),
+            OutputFormat::Json => b"json\n".as_slice(),
+            OutputFormat::Hocr => b"hocr\n".as_slice(),
+            OutputFormat::Alto => b"alto\n".as_slice(),
+        };
+        [format, &self.body].concat()
+    }
+
+    fn from_file_contents(contents: Vec<u8>) -> Option<CachedResponse> {
+        let newline = contents.iter().position(|b| *b == b'\n')?;
+        let format = match &contents[..newline] {
+            b"text" => OutputFormat::Text,
+            b"json" => OutputFormat::Json,
+            b"hocr" => OutputFormat::Hocr,
+            b"alto" => OutputFormat::Alto,
+            _ => return None,
+        };
+        Some(CachedResponse {
+            format,
+            body: contents[newline + 1..].to_vec(),
+        })
+    }
+}
+
+/// Limits of a [ResultCache].
+#[derive(Clone, Debug)]
+pub struct ResultCacheLimits {
+    /// Maximum number of responses kept in memory. 0 disables the cache.
+    pub max_entries: usize,
+
+    /// Maximum total size of the responses kept in memory, in bytes.
+    pub max_bytes: usize,
+
+    /// Time after which a response expires, or `None` to keep responses
+    /// until they are evicted.
+    pub ttl: Option<Duration>,
+
+    /// Directory that responses evicted from memory are moved to.
+    pub dir: Option<PathBuf>,
+
+    /// Maximum total size of the responses kept in `dir`, in bytes.
+    pub dir_max_bytes: u64,
+}
+
+struct MemoryEntry {
+    response: CachedResponse,
+    created: SystemTime,
+    last_used: u64,
+}
+
+struct DiskEntry {
+    size: u64,
+    created: SystemTime,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    memory: HashMap<String, MemoryEntry>,
+    memory_bytes: usize,
+    disk: HashMap<String, DiskEntry>,
+    disk_bytes: u64,
+
+    /// Counter used to order entries by last use.
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+/// Change to the cache directory, decided while the entries are locked and
+/// made after unlocking them so that the lock is never held during disk I/O.
+enum DiskOp {
+    Write {
+        key: String,
+        contents: Vec<u8>,
+        created: SystemTime,
+    },
+    Remove(String),
+}
+
+/// Snapshot of the contents of a [ResultCache].
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ResultCacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub disk_entries: usize,
+    pub disk_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Least-recently-used cache of `/process` responses, keyed by a hash of
+/// the request body and every option that affects the response.
+///
+/// Responses evicted from memory are moved to a directory on disk, if one is
+/// configured, from where they are evicted in turn when it is full. Methods
+/// that may read or write that directory block, so async code should call
+/// them with `web::block`.
+pub struct ResultCache {
+    limits: ResultCacheLimits,
+    entries: Mutex<Entries>,
+}
+
+/// Return the cache key for a request body and a description of the options
+/// that affect the response.
+pub fn cache_key(body: &[u8], options: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(options.as_bytes());
+    hasher.update([0]);
+    hasher.update(body);
+    format!("{:x}", hasher.finalize())
+}
+
+impl ResultCache {
+    /// Create a cache. Responses left in `limits.dir` by a previous run are
+    /// reused.
+    pub fn new(limits: ResultCacheLimits) -> Result<ResultCache, anyhow::Error> {
+        let mut entries = Entries::default();
+        if let Some(dir) = &limits.dir {
+            fs::create_dir_all(dir)?;
+            for file in fs::read_dir(dir)? {
+                let file = file?;
+                let metadata = file.metadata()?;
+                let Some(key) = file.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+                if !metadata.is_file() || !is_cache_key(&key) {
+                    continue;
+                }
+                entries.disk_bytes += metadata.len();
+                entries.disk.insert(
+                    key,
+                    DiskEntry {
+                        size: metadata.len(),
+                        created: metadata.modified()?,
+                        last_used: 0,
+                    },
+                );
+            }
+        }
+        let cache = ResultCache {
+            limits,
+            entries: Mutex::new(entries),
+        };
+        let ops = cache.evict(&mut cache.entries.lock().unwrap_or_else(|err| err.into_inner()));
+        cache.apply(ops);
+        Ok(cache)
+    }
+
+    /// Return whether the cache is enabled.
+    pub fn enabled(&self) -> bool {
+        self.limits.max_entries > 0
+    }
+
+    /// Look up a response, counting a hit or a miss.
+    pub fn get(&self, key: &str) -> Option<CachedResponse> {
+        if !self.enabled() {
+            return None;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries.clock += 1;
+        let clock = entries.clock;
+
+        let mut ops = Vec::new();
+        let response = if let Some(entry) = entries.memory.get_mut(key) {
+            if self.is_expired(entry.created) {
+                None
+            } else {
+                entry.last_used = clock;
+                Some(entry.response.clone())
+            }
+        } else if let Some(entry) = entries.disk.remove(key) {
+            // Move the response back into memory. The file is read without
+            // holding the lock.
+            entries.disk_bytes -= entry.size;
+            drop(entries);
+            let path = self.disk_path(key);
+            let response = fs::read(&path)
+                .ok()
+                .and_then(CachedResponse::from_file_contents)
+                .filter(|_| !self.is_expired(entry.created));
+            let _ = fs::remove_file(path);
+
+            entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+            if let Some(response) = &response {
+                ops = self.insert_memory(&mut entries, key, response.clone(), entry.created);
+            }
+            response
+        } else {
+            None
+        };
+
+        if response.is_some() {
+            entries.hits += 1;
+        } else {
+            entries.misses += 1;
+        }
+        drop(entries);
+        self.apply(ops);
+        response
+    }
+
+    /// Add a response to the cache, evicting the least recently used
+    /// responses if it is full.
+    pub fn insert(&self, key: &str, response: CachedResponse) {
+        if !self.enabled() || response.body.len() > self.limits.max_bytes {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        let ops = self.insert_memory(&mut entries, key, response, SystemTime::now());
+        drop(entries);
+        self.apply(ops);
+    }
+
+    /// Remove every response from memory and disk, and return how many were
+    /// removed.
+    pub fn flush(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        let count = entries.memory.len() + entries.disk.len();
+        let ops: Vec<DiskOp> = entries
+            .disk
+            .drain()
+            .map(|(key, _)| DiskOp::Remove(key))
+            .collect();
+        entries.memory.clear();
+        entries.memory_bytes = 0;
+        entries.disk_bytes = 0;
+        drop(entries);
+        self.apply(ops);
+        count
+    }
+
+    pub fn stats(&self) -> ResultCacheStats {
+        let entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        ResultCacheStats {
+            entries: entries.memory.len(),
+            bytes: entries.memory_bytes,
+            disk_entries: entries.disk.len(),
+            disk_bytes: entries.disk_bytes,
+            hits: entries.hits,
+            misses: entries.misses,
+        }
+    }
+
+    fn insert_memory(
+        &self,
+        entries: &mut Entries,
+        key: &str,
+        response: CachedResponse,
+        created: SystemTime,
+    ) -> Vec<DiskOp> {
+        entries.clock += 1;
+        entries.memory_bytes += response.body.len();
+        let entry = MemoryEntry {
+            response,
+            created,
+            last_used: entries.clock,
+        };
+        if let Some(old) = entries.memory.insert(key.to_string(), entry) {
+            entries.memory_bytes -= old.response.body.len();
+        }
+        self.evict(entries)
+    }
+
+    /// Drop expired responses and evict the least recently used ones until
+    /// the cache is within its limits, and return the changes to make to the
+    /// cache directory.
+    fn evict(&self, entries: &mut Entries) -> Vec<DiskOp> {
+        let mut ops = Vec::new();
+        let expired: Vec<String> = entries
+            .memory
+            .iter()
+            .filter(|(_, entry)| self.is_expired(entry.created))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            if let Some(entry) = entries.memory.remove(&key) {
+                entries.memory_bytes -= entry.response.body.len();
+            }
+        }
+
+        while entries.memory.len() > self.limits.max_entries
+            || entries.memory_bytes > self.limits.max_bytes
+        {
+            let Some(key) =
+                least_recently_used(entries.memory.iter().map(|(k, e)| (k, e.last_used)))
+            else {
+                break;
+            };
+            let entry = entries.memory.remove(&key).unwrap();
+            entries.memory_bytes -= entry.response.body.len();
+            self.spill(entries, key, entry, &mut ops);
+        }
+
+        let expired: Vec<String> = entries
+            .disk
+            .iter()
+            .filter(|(_, entry)| self.is_expired(entry.created))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            remove_disk(entries, key, &mut ops);
+        }
+        while entries.disk_bytes > self.limits.dir_max_bytes {
+            let Some(key) = least_recently_used(entries.disk.iter().map(|(k, e)| (k, e.last_used)))
+            else {
+                break;
+            };
+            remove_disk(entries, key, &mut ops);
+        }
+        ops
+    }
+
+    /// Move a response evicted from memory to disk, if enabled.
+    ///
+    /// The entry is recorded straight away and the file written later by
+    /// [ResultCache::apply].
+    fn spill(&self, entries: &mut Entries, key: String, entry: MemoryEntry, ops: &mut Vec<DiskOp>) {
+        if self.limits.dir.is_none() {
+            return;
+        }
+        let contents = entry.response.to_file_contents();
+        let size = contents.len() as u64;
+        if size > self.limits.dir_max_bytes {
+            return;
+        }
+        entries.disk_bytes += size;
+        entries.disk.insert(
+            key.clone(),
+            DiskEntry {
+                size,
+                created: entry.created,
+                last_used: entry.last_used,
+            },
+        );
+        ops.push(DiskOp::Write {
+            key,
+            contents,
+            created: entry.created,
+        });
+    }
+
+    /// Make changes to the cache directory. Must be called without holding
+    /// the lock on the entries.
+    fn apply(&self, ops: Vec<DiskOp>) {
+        for op in ops {
+            match op {
+                DiskOp::Write {
+                    key,
+                    contents,
+                    created,
+                } => {
+                    let path = self.disk_path(&key);
+                    let result = write_atomic(&path, &contents);
+                    if let Err(err) = &result {
+                        warn!(
+                            "Failed to write cached response {}: {}",
+                            path.display(),
+                            err
+                        );
+                    }
+
+                    // The entry may have been read back or evicted while the
+                    // file was being written.
+                    let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+                    let current = entries
+                        .disk
+                        .get(&key)
+                        .is_some_and(|entry| entry.created == created);
+                    if result.is_err() && current {
+                        let entry = entries.disk.remove(&key).unwrap();
+                        entries.disk_bytes -= entry.size;
+                    } else if result.is_ok() && !current {
+                        drop(entries);
+                        let _ = fs::remove_file(path);
+                    }
+                }
+                DiskOp::Remove(key) => {
+                    let _ = fs::remove_file(self.disk_path(&key));
+                }
+            }
+        }
+    }
+
+    fn disk_path(&self, key: &str) -> PathBuf {
+        self.limits
+            .dir
+            .as_deref()
+            .unwrap_or(Path::new("."))
+            .join(key)
+    }
+
+    fn is_expired(&self, created: SystemTime) -> bool {
+        self.limits.ttl.is_some_and(|ttl| {
+            created
+                .elapsed()
+                .map(|elapsed| elapsed > ttl)
+                .unwrap_or(false)
+        })
+    }
+}
+
+/// Forget a response on disk and queue the removal of its file.
+fn remove_disk(entries: &mut Entries, key: String, ops: &mut Vec<DiskOp>) {
+    if let Some(entry) = entries.disk.remove(&key) {
+        entries.disk_bytes -= entry.size;
+        ops.push(DiskOp::Remove(key));
+    }
+}
+
+fn least_recently_used<'a>(entries: impl Iterator<Item = (&'a String, u64)>) -> Option<String> {
+    entries
+        .min_by_key(|(_, last_used)| *last_used)
+        .map(|(key, _)| key.clone())
+}
+
+/// Return whether a file name in the cache directory is a cache key.
+fn is_cache_key(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::thread;
+    use std::time::Duration;
+
+    use frame_ocr::output::OutputFormat;
+
+    use super::{cache_key, CachedResponse, ResultCache, ResultCacheLimits};
+
+    fn limits(max_entries: usize, dir: Option<&Path>) -> ResultCacheLimits {
+        ResultCacheLimits {
+            max_entries,
+            max_bytes: 1024,
+            ttl: None,
+            dir: dir.map(Path::to_path_buf),
+            dir_max_bytes: 1024,
+        }
+    }
+
+    fn response(body: &str) -> CachedResponse {
+        CachedResponse {
+            format: OutputFormat::Json,
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn body(response: Option<CachedResponse>) -> Option<String> {
+        response.map(|r| String::from_utf8(r.body).unwrap())
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let key = cache_key(b"image", "format=json");
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, cache_key(b"image", "format=json"));
+        assert_ne!(key, cache_key(b"image", "format=text"));
+        assert_ne!(key, cache_key(b"other", "format=json"));
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = ResultCache::new(limits(2, None)).unwrap();
+        let [a, b, c] = ["a", "b", "c"].map(|k| cache_key(k.as_bytes(), ""));
+
+        cache.insert(&a, response("a"));
+        cache.insert(&b, response("b"));
+        assert_eq!(body(cache.get(&a)).as_deref(), Some("a"));
+
+        // `b` is now the least recently used.
+        cache.insert(&c, response("c"));
+        assert_eq!(body(cache.get(&b)), None);
+        assert_eq!(body(cache.get(&a)).as_deref(), Some("a"));
+        assert_eq!(body(cache.get(&c)).as_deref(), Some("c"));
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes), (2, 2));
+        assert_eq!((stats.hits, stats.misses), (3, 1));
+    }
+
+    #[test]
+    fn test_evicts_by_size() {
+        let cache = ResultCache::new(ResultCacheLimits {
+            max_bytes: 10,
+            ..limits(10, None)
+        })
+        .unwrap();
+        let [a, b, c] = ["a", "b", "c"].map(|k| cache_key(k.as_bytes(), ""));
+
+        cache.insert(&a, response("aaaa"));
+        cache.insert(&b, response("bbbb"));
+        cache.insert(&c, response("cccc"));
+        assert_eq!(body(cache.get(&a)), None);
+        assert_eq!(cache.stats().bytes, 8);
+
+        // Responses larger than the whole cache are not stored.
+        cache.insert(&a, response("aaaaaaaaaaaa"));
+        assert_eq!(body(cache.get(&a)), None);
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let cache = ResultCache::new(limits(0, None)).unwrap();
+        let key = cache_key(b"a", "");
+        cache.insert(&key, response("a"));
+        assert_eq!(body(cache.get(&key)), None);
+        assert_eq!(cache.stats().misses, 0);
+    }
+
+    #[test]
+    fn test_expires_responses() {
+        let cache = ResultCache::new(ResultCacheLimits {
+            ttl: Some(Duration::from_millis(10)),
+            ..limits(10, None)
+        })
+        .unwrap();
+        let key = cache_key(b"a", "");
+        cache.insert(&key, response("a"));
+        assert!(cache.get(&key).is_some());
+        thread::sleep(Duration::from_millis(20));
+        assert!(cache.get(&key).is_none());
+    }
+
+    #[test]
+    fn test_spills_to_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResultCache::new(limits(1, Some(dir.path()))).unwrap();
+        let [a, b] = ["a", "b"].map(|k| cache_key(k.as_bytes(), ""));
+
+        cache.insert(&a, response("a"));
+        cache.insert(
+            &b,
+            CachedResponse {
+                format: OutputFormat::Alto,
+                body: b"<alto/>".to_vec(),
+            },
+        );
+
+        // `a` was evicted from memory to disk.
+        assert!(dir.path().join(&a).is_file());
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.disk_entries), (1, 1));
+
+        // Reading `a` moves it back into memory, and `b` to disk.
+        let read = cache.get(&a).unwrap();
+        assert_eq!(read.format, OutputFormat::Json);
+        assert_eq!(read.body, b"a");
+        assert!(!dir.path().join(&a).exists());
+        assert!(dir.path().join(&b).is_file());
+
+        // Responses on disk are reused by a new cache.
+        drop(cache);
+        let cache = ResultCache::new(limits(1, Some(dir.path()))).unwrap();
+        assert_eq!(cache.stats().disk_entries, 1);
+        let read = cache.get(&b).unwrap();
+        assert_eq!(read.format, OutputFormat::Alto);
+        assert_eq!(read.body, b"<alto/>");
+
+        assert_eq!(cache.flush(), 1);
+        assert!(!dir.path().join(&b).exists());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_limits_disk_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResultCache::new(ResultCacheLimits {
+            // Each file holds a "json\n" header and a 4 byte body.
+            dir_max_bytes: 20,
+            ..limits(1, Some(dir.path()))
+        })
+        .unwrap();
+        let keys = ["a", "b", "c", "d"].map(|k| cache_key(k.as_bytes(), ""));
+        for key in &keys {
+            cache.insert(key, response("body"));
+        }
+
+        // `d` is in memory, and `a` was evicted from disk to make space for
+        // `b` and `c`.
+        let stats = cache.stats();
+        assert_eq!((stats.disk_entries, stats.disk_bytes), (2, 18));
+        assert!(!dir.path().join(&keys[0]).exists());
+        assert!(dir.path().join(&keys[1]).is_file());
+        assert!(dir.path().join(&keys[2]).is_file());
+    }
+}
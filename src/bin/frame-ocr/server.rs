@@ -0,0 +1,461 @@
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+use actix_multipart::Multipart;
+use actix_web::http::header::{AsHeaderName, ContentType, ACCEPT, CONTENT_TYPE};
+use actix_web::http::KeepAlive;
+use actix_web::middleware::from_fn;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use frame_ocr::confidence::deserialize_min_confidence;
+use frame_ocr::engine::DecodeMode;
+use frame_ocr::output::{FormattedOutput, OutputFormat};
+use frame_ocr::pipeline::OcrOptions;
+use frame_ocr::preprocess::{deserialize_steps, PreprocessStep};
+use frame_ocr::roi::{deserialize_rois, Roi};
+use frame_ocr::sequence::SpanTracker;
+use frame_ocr::OcrService;
+use futures::stream::{self, StreamExt};
+use lazy_static::initialize;
+use serde::Deserialize;
+use tempfile::NamedTempFile;
+use tracing::{error, info, Span};
+
+use crate::app::{load_models, new_span_tracker, ocr_image, ocr_video, service, CONFIG, READINESS};
+use crate::batch::{parse_json_batch, read_multipart_batch, BatchItemResult, BatchResponse};
+use crate::error::ApiError;
+use crate::health::ReadyState;
+use crate::pool::{ComputePool, QueueFull};
+use crate::result_cache::{cache_key, CachedResponse, ResultCache};
+use crate::video::FrameSampling;
+use crate::{logging, metrics};
+
+lazy_static! {
+    static ref COMPUTE_POOL: ComputePool =
+        ComputePool::new(CONFIG.compute_threads, CONFIG.queue_size);
+    static ref RESULT_CACHE: ResultCache =
+        ResultCache::new(CONFIG.result_cache_limits()).expect("Failed to initialize result cache");
+}
+
+/// Query parameters accepted by the `/process` endpoint.
+#[derive(Deserialize)]
+struct ProcessParams {
+    format: Option<OutputFormat>,
+
+    /// Override the configured decode method for this request.
+    decode_method: Option<DecodeMode>,
+
+    /// Drop lines whose average character confidence is below this value.
+    #[serde(default, deserialize_with = "deserialize_min_confidence")]
+    min_confidence: Option<f32>,
+
+    /// Restrict OCR to these regions, eg. `subtitles:0%,80%,100%,20%`.
+    #[serde(default, deserialize_with = "deserialize_rois")]
+    roi: Option<Vec<Roi>>,
+
+    /// Preprocessing applied before OCR, eg. `scale:2,grayscale,invert`.
+    #[serde(default, deserialize_with = "deserialize_steps")]
+    preprocess: Option<Vec<PreprocessStep>>,
+
+    /// Detect and correct rotated or skewed text.
+    auto_rotate: Option<bool>,
+
+    /// Merge lines repeated across consecutive images into spans. Only used
+    /// by the batch and video endpoints.
+    dedupe: Option<bool>,
+}
+
+impl ProcessParams {
+    fn ocr_options(&self, output_format: OutputFormat) -> OcrOptions {
+        OcrOptions {
+            decode_method: self.decode_method,
+            score_confidence: output_format.includes_confidence(),
+            min_confidence: self.min_confidence,
+            preprocess: self.preprocess.clone().unwrap_or_default(),
+            auto_rotate: self.auto_rotate.unwrap_or(false),
+        }
+    }
+
+    /// Describe the options and models that affect the output for `format`,
+    /// for use in result cache keys.
+    ///
+    /// The models are identified by their digests, as well as their source,
+    /// so that cached responses are not reused after a model changes.
+    fn cache_options(
+        &self,
+        service: &OcrService,
+        format: OutputFormat,
+        content_type: Option<&str>,
+    ) -> String {
+        let detection_model = service.detection_model();
+        let recognition_model = service.recognition_model();
+        format!(
+            "format={:?};decode_method={};beam_width={};detection_model={}@{};recognition_model={}@{};min_confidence={:?};roi={:?};preprocess={:?};auto_rotate={:?};content_type={:?}",
+            format,
+            self.decode_method.unwrap_or(CONFIG.decode_method),
+            CONFIG.beam_width,
+            detection_model.source,
+            detection_model.sha256,
+            recognition_model.source,
+            recognition_model.sha256,
+            self.min_confidence,
+            self.roi,
+            self.preprocess,
+            self.auto_rotate,
+            content_type,
+        )
+    }
+
+    /// Return a tracker for merging repeated lines, if requested.
+    fn span_tracker(&self) -> Option<SpanTracker> {
+        self.dedupe.unwrap_or(CONFIG.dedupe).then(new_span_tracker)
+    }
+}
+
+/// Query parameters that control how frames are sampled from a video.
+#[derive(Deserialize)]
+struct VideoParams {
+    /// Seconds between sampled frames.
+    interval: Option<f64>,
+
+    /// Sample frames on scene changes whose score exceeds this threshold.
+    scene_threshold: Option<f64>,
+}
+
+impl VideoParams {
+    fn sampling(&self) -> Result<FrameSampling, ApiError> {
+        match (self.scene_threshold, self.interval) {
+            (Some(threshold), _) if !(0. ..=1.).contains(&threshold) => Err(
+                ApiError::InvalidRequest("scene_threshold must be between 0 and 1".to_string()),
+            ),
+            (Some(threshold), _) => Ok(FrameSampling::SceneChange(threshold)),
+            (None, Some(interval)) if !(interval.is_finite() && interval > 0.) => Err(
+                ApiError::InvalidRequest("interval must be greater than 0".to_string()),
+            ),
+            (None, Some(interval)) => Ok(FrameSampling::Interval(interval)),
+            (None, None) => Ok(CONFIG.frame_sampling()),
+        }
+    }
+}
+
+fn header_str(req: &HttpRequest, name: impl AsHeaderName) -> Option<&str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Liveness probe. Succeeds as long as the server is running, including
+/// while models are loading, but fails if loading the models failed.
+async fn healthz() -> HttpResponse {
+    match READINESS.state() {
+        ReadyState::Failed(error) => HttpResponse::ServiceUnavailable()
+            .json(serde_json::json!({ "status": "failed", "error": error })),
+        _ => HttpResponse::Ok().json(serde_json::json!({ "status": "ok" })),
+    }
+}
+
+/// Readiness probe. Succeeds once the models are loaded and passed the
+/// self-test.
+async fn readyz() -> HttpResponse {
+    let report = READINESS.report();
+    let mut response = if report.status == "ready" {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    response.json(report)
+}
+
+/// Report metrics in the Prometheus text format.
+async fn prometheus_metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render(COMPUTE_POOL.stats()))
+}
+
+/// Report the load on the compute pool, for autoscaling.
+async fn queue_stats() -> HttpResponse {
+    HttpResponse::Ok().json(COMPUTE_POOL.stats())
+}
+
+/// Report the size and hit rate of the result cache.
+async fn result_cache_stats() -> HttpResponse {
+    HttpResponse::Ok().json(RESULT_CACHE.stats())
+}
+
+/// Remove every response from the result cache.
+async fn flush_result_cache() -> Result<HttpResponse, ApiError> {
+    let flushed = web::block(|| RESULT_CACHE.flush()).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "flushed": flushed })))
+}
+
+/// Build a `/process` response, with an `X-Cache` header if the result cache
+/// is enabled.
+fn process_response(response: CachedResponse, cache_status: Option<&str>) -> HttpResponse {
+    let mut builder = HttpResponse::Ok();
+    match response.format {
+        OutputFormat::Text => {}
+        OutputFormat::Json => {
+            builder.content_type(ContentType::json());
+        }
+        OutputFormat::Hocr => {
+            builder.content_type("application/xhtml+xml; charset=utf-8");
+        }
+        OutputFormat::Alto => {
+            builder.content_type(ContentType::xml());
+        }
+    }
+    if let Some(status) = cache_status {
+        builder.insert_header(("X-Cache", status));
+    }
+    builder.body(response.body)
+}
+
+/// Run `f` on the compute pool and wait for its result.
+///
+/// Fails without queueing `f` if the models are not ready.
+async fn run_on_pool<T, F>(f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, ApiError> + Send + 'static,
+{
+    if !READINESS.is_ready() {
+        return Err(ApiError::NotReady {
+            retry_after: CONFIG.retry_after,
+        });
+    }
+    // Keep logging in the context of the request on the compute thread.
+    let span = Span::current();
+    let result = COMPUTE_POOL
+        .spawn(move || span.in_scope(f))
+        .map_err(|QueueFull| ApiError::QueueFull {
+            retry_after: CONFIG.retry_after,
+        })?;
+    result
+        .await
+        .map_err(|_| ApiError::Internal("Failed to process image".to_string()))?
+}
+
+/// Read a request body, enforcing the configured size limit.
+async fn read_body(payload: web::Payload) -> Result<web::Bytes, ApiError> {
+    payload
+        .to_bytes_limited(CONFIG.max_payload_size)
+        .await
+        .map_err(|_| ApiError::PayloadTooLarge {
+            limit: CONFIG.max_payload_size,
+        })?
+        .map_err(|err| ApiError::InvalidRequest(format!("Failed to read request body: {}", err)))
+}
+
+async fn process_image(
+    req: HttpRequest,
+    params: web::Query<ProcessParams>,
+    payload: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    let stream = read_body(payload).await?;
+    let content_type = header_str(&req, CONTENT_TYPE).map(|ct| ct.to_string());
+    let output_format = OutputFormat::negotiate(params.format, header_str(&req, ACCEPT));
+    let opts = params.ocr_options(output_format);
+
+    let key = if RESULT_CACHE.enabled() {
+        let options = params.cache_options(service()?, output_format, content_type.as_deref());
+        Some(cache_key(&stream, &options))
+    } else {
+        None
+    };
+    if let Some(key) = key.clone() {
+        // The cache may need to read from disk.
+        if let Some(response) = web::block(move || RESULT_CACHE.get(&key)).await? {
+            return Ok(process_response(response, Some("hit")));
+        }
+    }
+
+    let regions = params.into_inner().roi;
+    let output = run_on_pool(move || {
+        let result = ocr_image(&stream, content_type.as_deref(), regions.as_deref(), &opts)?;
+        Ok(result.format(output_format))
+    })
+    .await?;
+
+    let body = match output {
+        FormattedOutput::Text(text) | FormattedOutput::Hocr(text) | FormattedOutput::Alto(text) => {
+            text.into_bytes()
+        }
+        FormattedOutput::Json(output) => {
+            serde_json::to_vec(&output).expect("Failed to serialize output")
+        }
+    };
+    let response = CachedResponse {
+        format: output_format,
+        body,
+    };
+    let cache_status = key.is_some().then_some("miss");
+    if let Some(key) = key {
+        let response = response.clone();
+        web::block(move || RESULT_CACHE.insert(&key, response)).await?;
+    }
+    Ok(process_response(response, cache_status))
+}
+
+/// Process many images in one request.
+///
+/// The body is either `multipart/form-data` with one image per part, or a
+/// JSON array of base64-encoded images. Failures are reported per image.
+async fn process_batch(
+    req: HttpRequest,
+    params: web::Query<ProcessParams>,
+    payload: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    let content_type = header_str(&req, CONTENT_TYPE).unwrap_or_default();
+    let items = if content_type.starts_with("multipart/") {
+        let multipart = Multipart::new(req.headers(), payload);
+        read_multipart_batch(multipart, CONFIG.max_payload_size).await?
+    } else {
+        parse_json_batch(&read_body(payload).await?)?
+    };
+
+    // The response is always JSON. `format` selects whether each item
+    // contains plain text or structured output.
+    let output_format = params.format.unwrap_or_default();
+    let opts = params.ocr_options(output_format);
+    let mut spans = params.span_tracker();
+    let regions = params.into_inner().roi;
+
+    if !READINESS.is_ready() {
+        return Err(ApiError::NotReady {
+            retry_after: CONFIG.retry_after,
+        });
+    }
+
+    // Each image is a separate job. At most one job per compute thread is
+    // queued at a time, so a large batch waits for free workers instead of
+    // filling the queue, and images are processed roughly in frame order.
+    let jobs = items.into_iter().map(|item| {
+        let (opts, regions) = (opts.clone(), regions.clone());
+        async move {
+            let result = match item.data {
+                Ok(data) => {
+                    let content_type = item.content_type;
+                    run_on_pool(move || {
+                        let result =
+                            ocr_image(&data, content_type.as_deref(), regions.as_deref(), &opts)?;
+                        let output = result.format(output_format);
+                        Ok((result, output))
+                    })
+                    .await
+                }
+                Err(err) => Err(err),
+            };
+            (item.name, result)
+        }
+    });
+    let results = stream::iter(jobs)
+        .buffered(CONFIG.compute_threads)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .enumerate()
+        .map(|(index, (name, result))| {
+            if let Some(spans) = &mut spans {
+                // A failed image ends any spans that were open.
+                let lines = result.as_ref().map(|(r, _)| r.lines.as_slice());
+                spans.push_frame(index, None, lines.unwrap_or_default());
+            }
+            BatchItemResult::new(name, result.map(|(_, output)| output))
+        })
+        .collect();
+    let response = BatchResponse {
+        results,
+        spans: spans.map(SpanTracker::finish),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Process a video file.
+///
+/// The body is a video in any format ffmpeg can decode. Frames are sampled
+/// every `interval` seconds or on scene changes, and the response is a JSON
+/// timeline of the text in each sampled frame.
+async fn process_video(
+    params: web::Query<ProcessParams>,
+    video_params: web::Query<VideoParams>,
+    payload: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    let sampling = video_params.sampling()?;
+    let body = read_body(payload).await?;
+    let output_format = params.format.unwrap_or_default();
+    let opts = params.ocr_options(output_format);
+    let spans = params.span_tracker();
+    let regions = params.into_inner().roi;
+
+    let response = run_on_pool(move || {
+        // ffmpeg needs a seekable input for formats such as MP4.
+        let file = NamedTempFile::new()
+            .and_then(|mut file| file.write_all(&body).map(|_| file))
+            .map_err(|err| ApiError::Internal(format!("Failed to store video: {}", err)))?;
+        ocr_video(
+            file.path(),
+            sampling,
+            output_format,
+            regions.as_deref(),
+            &opts,
+            spans,
+        )
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Run the HTTP server until it is stopped.
+pub async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    info!(config = %serde_json::to_string(&*CONFIG)?, "Effective configuration");
+
+    initialize(&COMPUTE_POOL);
+    initialize(&RESULT_CACHE);
+
+    // Models are loaded in the background so that the server can report
+    // that it is alive but not ready while they are downloaded.
+    thread::Builder::new()
+        .name("model-loader".to_string())
+        .spawn(|| {
+            if let Err(err) = load_models() {
+                error!("Failed to load models: {:#}", err);
+            }
+        })?;
+
+    let keep_alive = match CONFIG.keep_alive {
+        0 => KeepAlive::Disabled,
+        secs => KeepAlive::Timeout(Duration::from_secs(secs)),
+    };
+    let mut server = HttpServer::new(|| {
+        App::new()
+            .wrap(from_fn(metrics::track_requests))
+            .wrap(from_fn(logging::request_context))
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|err, _req| ApiError::InvalidRequest(err.to_string()).into()),
+            )
+            .route("/process", web::post().to(process_image))
+            .route("/process/batch", web::post().to(process_batch))
+            .route("/process/video", web::post().to(process_video))
+            .route("/metrics", web::get().to(prometheus_metrics))
+            .route("/healthz", web::get().to(healthz))
+            .route("/readyz", web::get().to(readyz))
+            .route("/queue", web::get().to(queue_stats))
+            .route("/admin/cache", web::get().to(result_cache_stats))
+            .route("/admin/cache/flush", web::post().to(flush_result_cache))
+    })
+    .keep_alive(keep_alive);
+    if let Some(workers) = CONFIG.workers {
+        server = server.workers(workers);
+    }
+    for (addr, port) in CONFIG.bind_addrs() {
+        server = server.bind((addr, port))?;
+        info!("Starting server at http://{}:{}", addr, port);
+    }
+
+    server.run().await?;
+    Ok(())
+}
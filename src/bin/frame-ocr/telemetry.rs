@@ -0,0 +1,74 @@
+use std::env;
+
+use actix_web::http::header::HeaderMap;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+use opentelemetry_sdk::Resource;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use url::Url;
+
+/// Name reported to the collector unless `OTEL_SERVICE_NAME` is set.
+const SERVICE_NAME: &str = "frame-ocr";
+
+/// Path of the OTLP/HTTP endpoint for traces.
+const TRACES_PATH: &str = "/v1/traces";
+
+/// Return the URL that traces are sent to for an OTLP collector at
+/// `endpoint`.
+///
+/// A URL without a path, eg. `http://localhost:4318`, is taken to be the
+/// collector's base URL and gets the standard traces path appended.
+pub fn traces_url(endpoint: &str) -> Result<Url, url::ParseError> {
+    let mut url = Url::parse(endpoint)?;
+    if url.path() == "/" {
+        url.set_path(TRACES_PATH);
+    }
+    Ok(url)
+}
+
+/// Create a tracer that exports spans in batches to the OTLP/HTTP collector
+/// at `endpoint`.
+pub fn init_tracer(endpoint: &str) -> Result<(SdkTracerProvider, Tracer), anyhow::Error> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(traces_url(endpoint)?.as_str())
+        .build()?;
+
+    let mut resource = Resource::builder();
+    if env::var_os("OTEL_SERVICE_NAME").is_none() {
+        resource = resource.with_service_name(SERVICE_NAME);
+    }
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource.build())
+        .build();
+    let tracer = provider.tracer(SERVICE_NAME);
+    Ok((provider, tracer))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Make `span` a child of the trace that the caller is part of, as given by
+/// the W3C `traceparent` and `tracestate` headers.
+///
+/// This has no effect if trace export is disabled.
+pub fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+    if headers.contains_key("traceparent") {
+        let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+        span.set_parent(context);
+    }
+}
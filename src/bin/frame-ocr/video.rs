@@ -0,0 +1,270 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+
+use frame_ocr::output::FormattedOutput;
+use rten_tensor::NdTensor;
+use serde::Serialize;
+
+use frame_ocr::sequence::TextSpan;
+
+/// How frames are picked from a video.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameSampling {
+    /// Take the first frame, then the first frame at least this many seconds
+    /// after the previously taken one.
+    Interval(f64),
+
+    /// Take the first frame, then every frame whose scene change score
+    /// (between 0 and 1) exceeds this threshold.
+    SceneChange(f64),
+}
+
+impl FrameSampling {
+    /// Return the ffmpeg `select` filter expression for this sampling mode.
+    fn select_expr(&self) -> String {
+        match self {
+            FrameSampling::Interval(secs) => {
+                format!("isnan(prev_selected_t)+gte(t-prev_selected_t,{})", secs)
+            }
+            FrameSampling::SceneChange(threshold) => format!("eq(n,0)+gt(scene,{})", threshold),
+        }
+    }
+}
+
+/// Reasons a video could not be read.
+#[derive(Debug)]
+pub enum VideoError {
+    /// ffmpeg could not be started.
+    Spawn(io::Error),
+
+    /// Reading ffmpeg's output failed.
+    Io(io::Error),
+
+    /// ffmpeg failed, usually because the input is not a supported video.
+    Ffmpeg(String),
+
+    /// More frames were sampled than the given limit.
+    TooManyFrames(usize),
+}
+
+impl fmt::Display for VideoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VideoError::Spawn(err) => write!(f, "Failed to run ffmpeg: {}", err),
+            VideoError::Io(err) => write!(f, "Failed to read video frames: {}", err),
+            VideoError::Ffmpeg(msg) => write!(f, "Failed to decode video: {}", msg),
+            VideoError::TooManyFrames(limit) => {
+                write!(f, "Video has more than {} sampled frames", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VideoError {}
+
+/// A frame sampled from a video.
+pub struct VideoFrame {
+    /// Position of the frame among the sampled frames.
+    pub index: usize,
+
+    /// Presentation time of the frame, in seconds.
+    pub timestamp: f64,
+
+    /// HWC RGB image.
+    pub image: NdTensor<u8, 3>,
+}
+
+/// Size and timestamp of a frame, as reported by ffmpeg's `showinfo` filter.
+struct FrameInfo {
+    timestamp: f64,
+    width: usize,
+    height: usize,
+}
+
+impl FrameInfo {
+    /// Parse a `showinfo` log line, eg.
+    /// `[Parsed_showinfo_1 @ 0x..] n:0 pts:0 pts_time:0 ... s:640x360 ...`.
+    fn parse(line: &str) -> Option<FrameInfo> {
+        if !line.contains("Parsed_showinfo") {
+            return None;
+        }
+        let field = |name: &str| {
+            line.split_whitespace()
+                .find_map(|token| token.strip_prefix(name))
+        };
+        let timestamp = field("pts_time:")?.parse().ok()?;
+        let (width, height) = field("s:")?.split_once('x')?;
+        Some(FrameInfo {
+            timestamp,
+            width: width.parse().ok()?,
+            height: height.parse().ok()?,
+        })
+    }
+}
+
+/// Iterator over frames sampled from a video file by an ffmpeg subprocess.
+///
+/// ffmpeg writes the selected frames to stdout as raw RGB data and logs the
+/// size and timestamp of each one to stderr, which is read on a separate
+/// thread.
+pub struct FrameReader {
+    child: Child,
+    stdout: ChildStdout,
+    frame_info: Receiver<FrameInfo>,
+    stderr_thread: Option<JoinHandle<Option<String>>>,
+    next_index: usize,
+    max_frames: usize,
+    done: bool,
+}
+
+impl FrameReader {
+    /// Start decoding the video at `path` with the ffmpeg binary `ffmpeg`.
+    ///
+    /// The reader fails with [VideoError::TooManyFrames] if the video has
+    /// more than `max_frames` sampled frames.
+    pub fn open(
+        ffmpeg: &Path,
+        path: &Path,
+        sampling: FrameSampling,
+        max_frames: usize,
+    ) -> Result<FrameReader, VideoError> {
+        let filter = format!("select='{}',showinfo", sampling.select_expr());
+        let mut child = Command::new(ffmpeg)
+            .args(["-hide_banner", "-nostats", "-nostdin", "-loglevel", "info"])
+            .arg("-i")
+            .arg(path)
+            .args(["-an", "-sn", "-dn", "-vf", &filter])
+            .args(["-fps_mode", "passthrough"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24", "pipe:1"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(VideoError::Spawn)?;
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let (sender, frame_info) = mpsc::channel();
+        // Collects the frame info and returns the last diagnostic message, which
+        // describes the error if ffmpeg fails.
+        let stderr_thread = thread::spawn(move || {
+            let mut last_message = None;
+            for line in BufReader::new(stderr).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if let Some(info) = FrameInfo::parse(&line) {
+                    // The reader may have stopped early, in which case
+                    // further frames are ignored.
+                    let _ = sender.send(info);
+                } else if !line.contains("Parsed_showinfo") && !line.trim().is_empty() {
+                    last_message = Some(line);
+                }
+            }
+            last_message
+        });
+
+        Ok(FrameReader {
+            child,
+            stdout,
+            frame_info,
+            stderr_thread: Some(stderr_thread),
+            next_index: 0,
+            max_frames,
+            done: false,
+        })
+    }
+
+    /// Wait for ffmpeg to exit and return an error if it failed.
+    fn finish(&mut self) -> Result<(), VideoError> {
+        self.done = true;
+        let status = self.child.wait().map_err(VideoError::Io)?;
+        let last_message = self
+            .stderr_thread
+            .take()
+            .and_then(|thread| thread.join().ok())
+            .flatten();
+        if status.success() {
+            Ok(())
+        } else {
+            let msg = last_message.unwrap_or_else(|| format!("ffmpeg exited with {}", status));
+            Err(VideoError::Ffmpeg(msg))
+        }
+    }
+
+    fn read_frame(&mut self, info: FrameInfo) -> io::Result<VideoFrame> {
+        let mut data = vec![0; info.width * info.height * 3];
+        self.stdout.read_exact(&mut data)?;
+        let index = self.next_index;
+        self.next_index += 1;
+        Ok(VideoFrame {
+            index,
+            timestamp: info.timestamp,
+            image: NdTensor::from_data([info.height, info.width, 3], data),
+        })
+    }
+}
+
+impl Iterator for FrameReader {
+    type Item = Result<VideoFrame, VideoError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        // The `showinfo` line for a frame is logged before the frame is
+        // written, so the channel closing means there are no more frames.
+        let frame = match self.frame_info.recv() {
+            Ok(_) if self.next_index >= self.max_frames => {
+                // Dropping the reader stops ffmpeg.
+                return Some(Err(VideoError::TooManyFrames(self.max_frames)));
+            }
+            Ok(info) => self.read_frame(info),
+            Err(_) => return self.finish().err().map(Err),
+        };
+        match frame {
+            Ok(frame) => Some(Ok(frame)),
+            Err(err) => match self.finish() {
+                Ok(()) => Some(Err(VideoError::Io(err))),
+                Err(err) => Some(Err(err)),
+            },
+        }
+    }
+}
+
+impl Drop for FrameReader {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// OCR result for one sampled frame of a video.
+#[derive(Serialize)]
+pub struct VideoFrameResult {
+    /// Position of the frame among the sampled frames.
+    pub index: usize,
+
+    /// Presentation time of the frame, in seconds.
+    pub timestamp: f64,
+
+    pub output: FormattedOutput,
+}
+
+/// Response body of the video endpoint: a timeline of the text in each
+/// sampled frame, in presentation order.
+#[derive(Serialize)]
+pub struct VideoResponse {
+    pub frames: Vec<VideoFrameResult>,
+
+    /// Lines merged across consecutive frames, if requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spans: Option<Vec<TextSpan>>,
+}
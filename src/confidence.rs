@@ -0,0 +1,195 @@
+use anyhow::anyhow;
+use ocrs::{OcrEngine, OcrInput, TextItem, TextLine};
+use rten::{Model, NodeId};
+use rten_imageproc::{bounding_rect, RotatedRect};
+use rten_tensor::prelude::*;
+use rten_tensor::{NdTensor, Tensor};
+use serde::{Deserialize, Deserializer};
+
+/// Alphabet of the default ocrs recognition model.
+///
+/// ocrs does not expose the alphabet it decodes with, so this is a copy of
+/// it. It must match the recognition model: a model trained with a different
+/// alphabet has a different number of output classes, which
+/// [ConfidenceScorer::score_line] reports as an error rather than returning
+/// scores for the wrong characters. The "E" before "ABCDE" stands in for the
+/// EUR symbol.
+const DEFAULT_ALPHABET: &str = " 0123456789!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~EABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Computes confidence scores for characters recognized by an [OcrEngine].
+///
+/// The engine only returns the decoded characters, so the scorer runs its own
+/// copy of the recognition model over each line and reads off the probability
+/// of each recognized character at the output steps covered by its bounding
+/// rect.
+///
+/// Scores are only available for recognition models that use the alphabet of
+/// the default ocrs model.
+pub struct ConfidenceScorer {
+    model: Model,
+    input_id: NodeId,
+    output_id: NodeId,
+}
+
+impl ConfidenceScorer {
+    /// Create a scorer from the same recognition model that the engine uses.
+    ///
+    /// The model must use the default ocrs alphabet.
+    pub fn from_model(model: Model) -> Result<ConfidenceScorer, anyhow::Error> {
+        let input_id = model
+            .input_ids()
+            .first()
+            .copied()
+            .ok_or(anyhow!("recognition model has no inputs"))?;
+        let output_id = model
+            .output_ids()
+            .first()
+            .copied()
+            .ok_or(anyhow!("recognition model has no outputs"))?;
+        Ok(ConfidenceScorer {
+            model,
+            input_id,
+            output_id,
+        })
+    }
+
+    /// Return the confidence, in the range [0, 1], of each character in
+    /// `line`.
+    ///
+    /// `word_rects` are the word rects from [OcrEngine::find_text_lines] that
+    /// `line` was recognized from.
+    pub fn score_line(
+        &self,
+        engine: &OcrEngine,
+        input: &OcrInput,
+        word_rects: &[RotatedRect],
+        line: &TextLine,
+    ) -> Result<Vec<f32>, anyhow::Error> {
+        let line_image = engine.prepare_recognition_input(input, word_rects)?;
+        let [height, width] = line_image.shape();
+        let line_image: Tensor<f32> = line_image.into_shape([1, 1, height, width]).into();
+
+        let [output] = self.model.run_n(
+            vec![(self.input_id, (&line_image).into())],
+            [self.output_id],
+            None,
+        )?;
+        // Log probabilities with shape [seq, batch, class].
+        let log_probs: NdTensor<f32, 3> = output
+            .try_into()
+            .map_err(|_| anyhow!("expected recognition output to have 3 dims"))?;
+        // Class `0` is the CTC blank, followed by one class per character.
+        let classes = DEFAULT_ALPHABET.chars().count() + 1;
+        if log_probs.size(2) != classes {
+            return Err(anyhow!(
+                "recognition model has {} output classes, expected {} for the default alphabet",
+                log_probs.size(2),
+                classes
+            ));
+        }
+        let seq_len = log_probs.size(0);
+        if seq_len == 0 {
+            return Ok(vec![0.; line.chars().len()]);
+        }
+
+        let line_rect = bounding_rect(word_rects.iter())
+            .ok_or(anyhow!("line has no words"))?
+            .integral_bounding_rect();
+        let x_scale = line_rect.width().max(1) as f32 / width as f32;
+        let downsample_factor = (width as f32 / seq_len as f32).round().max(1.);
+        let step_for_x = |x: i32| {
+            let step = ((x - line_rect.left()) as f32 / x_scale / downsample_factor) as usize;
+            step.min(seq_len - 1)
+        };
+
+        let scores = line
+            .chars()
+            .iter()
+            .map(|c| {
+                let start = step_for_x(c.rect.left());
+                let end = step_for_x(c.rect.right() - 1).max(start);
+                let label = DEFAULT_ALPHABET.chars().position(|ch| ch == c.char);
+                (start..=end)
+                    .map(|step| match label {
+                        // Label `0` is the CTC blank, so the character at
+                        // index `i` of the alphabet has label `i + 1`.
+                        Some(index) => log_probs[[step, 0, index + 1]],
+                        None => (1..log_probs.size(2))
+                            .map(|label| log_probs[[step, 0, label]])
+                            .fold(f32::NEG_INFINITY, f32::max),
+                    })
+                    .fold(f32::NEG_INFINITY, f32::max)
+                    .exp()
+            })
+            .collect();
+
+        Ok(scores)
+    }
+}
+
+/// Average the confidence scores of the non-space characters in a line or
+/// word.
+pub fn mean_confidence(scores: impl Iterator<Item = (char, f32)>) -> f32 {
+    let (sum, count) = scores
+        .filter(|(c, _)| *c != ' ')
+        .fold((0., 0), |(sum, count), (_, score)| (sum + score, count + 1));
+    if count == 0 {
+        0.
+    } else {
+        sum / count as f32
+    }
+}
+
+/// Parse a `min_confidence` threshold, which must be a number from 0 to 1.
+pub fn parse_min_confidence(s: &str) -> Result<f32, String> {
+    let value: f32 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid min_confidence \"{}\": expected a number", s))?;
+    if !(0. ..=1.).contains(&value) {
+        return Err(format!(
+            "invalid min_confidence \"{}\": must be between 0 and 1",
+            s
+        ));
+    }
+    Ok(value)
+}
+
+/// Deserialize an optional `min_confidence` query parameter with
+/// [parse_min_confidence].
+pub fn deserialize_min_confidence<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f32>, D::Error> {
+    let value: Option<String> = Option::deserialize(deserializer)?;
+    value
+        .map(|s| parse_min_confidence(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mean_confidence, parse_min_confidence};
+
+    #[test]
+    fn test_mean_confidence() {
+        let scores =
+            |text: &str, scores: &[f32]| mean_confidence(text.chars().zip(scores.iter().copied()));
+        assert_eq!(scores("ab", &[0.5, 1.]), 0.75);
+
+        // Spaces are ignored.
+        assert_eq!(scores("a b", &[0.5, 0., 1.]), 0.75);
+        assert_eq!(scores("", &[]), 0.);
+        assert_eq!(scores("  ", &[1., 1.]), 0.);
+    }
+
+    #[test]
+    fn test_parse_min_confidence() {
+        assert_eq!(parse_min_confidence("0"), Ok(0.));
+        assert_eq!(parse_min_confidence(" 0.8 "), Ok(0.8));
+        assert_eq!(parse_min_confidence("1"), Ok(1.));
+
+        for value in ["", "high", "-0.1", "1.01", "2", "NaN", "inf"] {
+            assert!(parse_min_confidence(value).is_err(), "{}", value);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt;
+use std::io::Cursor;
+
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageReader};
+use rten_tensor::NdTensor;
+use serde::Serialize;
+
+/// Reasons an uploaded image could not be decoded.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The image format was identified, but decoding it is not supported.
+    Unsupported(ImageFormat),
+
+    /// Neither the image data nor the declared content type identified a
+    /// known image format.
+    Unrecognized,
+
+    /// The image format is supported, but the data could not be decoded.
+    Invalid(ImageError),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Unsupported(format) => write!(
+                f,
+                "Unsupported image format: {:?} ({})",
+                format,
+                format.to_mime_type()
+            ),
+            DecodeError::Unrecognized => write!(f, "Unrecognized image format"),
+            DecodeError::Invalid(err) => write!(f, "Failed to load image: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Determine the format of an uploaded image.
+///
+/// The format is sniffed from the leading "magic bytes" of `data`. If that
+/// fails, the MIME type from the request's `Content-Type` header is used as a
+/// fallback.
+pub fn detect_format(data: &[u8], content_type: Option<&str>) -> Option<ImageFormat> {
+    image::guess_format(data).ok().or_else(|| {
+        content_type
+            .and_then(|ct| ct.split(';').next())
+            .map(|mime| mime.trim().to_ascii_lowercase())
+            .and_then(ImageFormat::from_mime_type)
+    })
+}
+
+/// Orientation stored in an image's EXIF metadata, and the transform that
+/// was applied to the pixels to honor it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct ExifOrientation {
+    /// EXIF orientation tag value, from 1 to 8.
+    pub original: u8,
+
+    /// Transform applied to the decoded image.
+    pub applied: &'static str,
+}
+
+impl From<Orientation> for ExifOrientation {
+    fn from(orientation: Orientation) -> Self {
+        let applied = match orientation {
+            Orientation::NoTransforms => "none",
+            Orientation::Rotate90 => "rotate_90",
+            Orientation::Rotate180 => "rotate_180",
+            Orientation::Rotate270 => "rotate_270",
+            Orientation::FlipHorizontal => "flip_horizontal",
+            Orientation::FlipVertical => "flip_vertical",
+            Orientation::Rotate90FlipH => "rotate_90_flip_horizontal",
+            Orientation::Rotate270FlipH => "rotate_270_flip_horizontal",
+        };
+        ExifOrientation {
+            original: orientation.to_exif(),
+            applied,
+        }
+    }
+}
+
+/// A decoded image, upright according to its EXIF orientation.
+pub struct DecodedImage {
+    pub image: DynamicImage,
+
+    /// The orientation that was applied, if the image was not already
+    /// upright.
+    pub orientation: Option<ExifOrientation>,
+}
+
+/// Decode an uploaded image, sniffing its format from the data and
+/// `content_type`.
+///
+/// Phone cameras store photos sideways and record how to display them in
+/// EXIF metadata (JPEG, WebP and TIFF), so the orientation is applied to the
+/// pixels after decoding.
+pub fn decode_image(data: &[u8], content_type: Option<&str>) -> Result<DecodedImage, DecodeError> {
+    let format = detect_format(data, content_type).ok_or(DecodeError::Unrecognized)?;
+    if !format.reading_enabled() {
+        return Err(DecodeError::Unsupported(format));
+    }
+    let map_err = |err| match err {
+        ImageError::Unsupported(_) => DecodeError::Unsupported(format),
+        err => DecodeError::Invalid(err),
+    };
+
+    let mut decoder = ImageReader::with_format(Cursor::new(data), format)
+        .into_decoder()
+        .map_err(map_err)?;
+    // Invalid or missing metadata should not prevent reading the image.
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder).map_err(map_err)?;
+    image.apply_orientation(orientation);
+
+    Ok(DecodedImage {
+        image,
+        orientation: (orientation != Orientation::NoTransforms).then(|| orientation.into()),
+    })
+}
+
+/// Convert a decoded image to an HWC RGB tensor for use with the OCR engine.
+pub fn image_to_tensor(image: DynamicImage) -> NdTensor<u8, 3> {
+    let image = image.into_rgb8();
+    let (width, height) = image.dimensions();
+    let in_chans = 3;
+    NdTensor::from_data(
+        [height as usize, width as usize, in_chans],
+        image.into_vec(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use rten_tensor::prelude::*;
+
+    use super::{decode_image, detect_format, image_to_tensor, DecodeError};
+
+    /// Encode a small RGB image in `format`.
+    fn encode(format: ImageFormat) -> Vec<u8> {
+        let image = RgbImage::from_fn(4, 3, |x, y| image::Rgb([x as u8 * 10, y as u8 * 10, 255]));
+        let mut data = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(image)
+            .write_to(&mut data, format)
+            .unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let png = encode(ImageFormat::Png);
+        let jpeg = encode(ImageFormat::Jpeg);
+
+        // The data takes precedence over the content type.
+        assert_eq!(detect_format(&png, None), Some(ImageFormat::Png));
+        assert_eq!(
+            detect_format(&jpeg, Some("image/png")),
+            Some(ImageFormat::Jpeg)
+        );
+
+        // The content type is used if the data is not recognized.
+        assert_eq!(
+            detect_format(b"garbage", Some("Image/WebP; charset=binary")),
+            Some(ImageFormat::WebP)
+        );
+        assert_eq!(detect_format(b"garbage", Some("text/plain")), None);
+        assert_eq!(detect_format(b"garbage", None), None);
+    }
+
+    #[test]
+    fn test_decode_image() {
+        for format in [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Bmp] {
+            let decoded = decode_image(&encode(format), None).unwrap();
+            assert_eq!(
+                (decoded.image.width(), decoded.image.height()),
+                (4, 3),
+                "{:?}",
+                format
+            );
+            assert!(decoded.orientation.is_none());
+        }
+    }
+
+    #[test]
+    fn test_decode_image_errors() {
+        assert!(matches!(
+            decode_image(b"garbage", None),
+            Err(DecodeError::Unrecognized)
+        ));
+
+        // QOI is recognized, but support for it is not enabled.
+        assert!(matches!(
+            decode_image(b"qoif\0\0\0\x04\0\0\0\x03\x03\0", None),
+            Err(DecodeError::Unsupported(ImageFormat::Qoi))
+        ));
+
+        // A truncated PNG.
+        let png = encode(ImageFormat::Png);
+        assert!(matches!(
+            decode_image(&png[..png.len() / 2], None),
+            Err(DecodeError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_image_to_tensor() {
+        let decoded = decode_image(&encode(ImageFormat::Png), None).unwrap();
+        let tensor = image_to_tensor(decoded.image);
+        assert_eq!(tensor.shape(), [3, 4, 3]);
+        assert_eq!(tensor[[2, 3, 0]], 30);
+        assert_eq!(tensor[[2, 3, 1]], 20);
+        assert_eq!(tensor[[2, 3, 2]], 255);
+    }
+}
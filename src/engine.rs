@@ -0,0 +1,231 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::Context;
+use ocrs::{DecodeMethod, OcrEngine, OcrEngineParams};
+use rten::Model;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::models::{
+    load_model_file, load_verified_model, LoadedModel, ModelSource, DETECTION_MODEL,
+    RECOGNITION_MODEL,
+};
+
+/// Method used to decode the output of the text recognition model.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "server", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum DecodeMode {
+    /// Pick the most likely character at each step. This is the fastest
+    /// method.
+    #[default]
+    Greedy,
+
+    /// Beam search, which is slower but can be more accurate.
+    #[cfg_attr(feature = "server", value(name = "beam_search", alias = "beam"))]
+    BeamSearch,
+}
+
+impl fmt::Display for DecodeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeMode::Greedy => write!(f, "greedy"),
+            DecodeMode::BeamSearch => write!(f, "beam_search"),
+        }
+    }
+}
+
+/// Models and decoding settings used to construct the OCR engines.
+#[derive(Clone, Copy, Debug)]
+pub struct EngineConfig<'a> {
+    pub detection_model: ModelSource<'a>,
+
+    /// Expected SHA-256 digest (hex) of the text detection model.
+    pub detection_model_sha256: Option<&'a str>,
+
+    pub recognition_model: ModelSource<'a>,
+
+    /// Expected SHA-256 digest (hex) of the text recognition model.
+    pub recognition_model_sha256: Option<&'a str>,
+
+    /// Decode method used when a request does not specify one.
+    pub decode_method: DecodeMode,
+
+    /// Beam width used for beam search decoding.
+    pub beam_width: u32,
+}
+
+impl Default for EngineConfig<'_> {
+    /// Use the published ocrs models, which are downloaded on first use.
+    fn default() -> Self {
+        EngineConfig {
+            detection_model: ModelSource::Url(DETECTION_MODEL),
+            detection_model_sha256: None,
+            recognition_model: ModelSource::Url(RECOGNITION_MODEL),
+            recognition_model_sha256: None,
+            decode_method: DecodeMode::default(),
+            beam_width: 10,
+        }
+    }
+}
+
+/// OCR engines for each supported decode method.
+///
+/// ocrs fixes the decode method when an engine is constructed and takes
+/// ownership of its models, so the models cannot be shared and each variant
+/// needs its own copy of the recognition model. The engine for the default
+/// decode method is built up front and is the only one with the detection
+/// model. The other variant is only used for recognition, which works on any
+/// engine's prepared input, and is built when it is first requested from the
+/// recognition model file, after checking its digest again.
+pub struct OcrEngines {
+    default_mode: DecodeMode,
+    default_engine: OcrEngine,
+
+    /// Engine for the decode method that is not the default.
+    other_engine: OnceLock<OcrEngine>,
+
+    /// Held while building `other_engine`, so it is only built once.
+    build_lock: Mutex<()>,
+
+    recognition_model: ModelFile,
+    detection_model: ModelFile,
+    beam_width: u32,
+}
+
+/// Local file and digest of a loaded model.
+#[derive(Clone, Debug)]
+pub struct ModelFile {
+    /// Where the model was loaded from, as configured.
+    pub source: String,
+
+    pub path: PathBuf,
+
+    /// Hex-encoded SHA-256 digest of the file.
+    pub sha256: String,
+}
+
+impl ModelFile {
+    fn new(source: ModelSource, loaded: &LoadedModel) -> ModelFile {
+        ModelFile {
+            source: source.to_string(),
+            path: loaded.path.clone(),
+            sha256: loaded.sha256.clone(),
+        }
+    }
+}
+
+impl OcrEngines {
+    /// Load models and construct the engine for the default decode method.
+    pub fn load(config: &EngineConfig) -> Result<OcrEngines, anyhow::Error> {
+        let detection = load_model_file(config.detection_model, config.detection_model_sha256)
+            .with_context(|| {
+                format!(
+                    "Failed to load text detection model from {}",
+                    config.detection_model
+                )
+            })?;
+        let recognition =
+            load_model_file(config.recognition_model, config.recognition_model_sha256)
+                .with_context(|| {
+                    format!(
+                        "Failed to load text recognition model from {}",
+                        config.recognition_model
+                    )
+                })?;
+        let detection_model = ModelFile::new(config.detection_model, &detection);
+        let recognition_model = ModelFile::new(config.recognition_model, &recognition);
+
+        let default_engine = new_engine(
+            config.decode_method,
+            config.beam_width,
+            Some(detection.model),
+            recognition.model,
+        )?;
+        Ok(OcrEngines {
+            default_mode: config.decode_method,
+            default_engine,
+            other_engine: OnceLock::new(),
+            build_lock: Mutex::new(()),
+            recognition_model,
+            detection_model,
+            beam_width: config.beam_width,
+        })
+    }
+
+    /// Return the engine used to prepare inputs and detect text.
+    pub fn detector(&self) -> &OcrEngine {
+        &self.default_engine
+    }
+
+    /// Return the engine that recognizes text using `mode`, or the default
+    /// decode method if `None`.
+    ///
+    /// The engine for a method other than the default is built on first use,
+    /// which loads another copy of the recognition model.
+    pub fn recognizer(&self, mode: Option<DecodeMode>) -> Result<&OcrEngine, anyhow::Error> {
+        let mode = mode.unwrap_or(self.default_mode);
+        if mode == self.default_mode {
+            return Ok(&self.default_engine);
+        }
+        if let Some(engine) = self.other_engine.get() {
+            return Ok(engine);
+        }
+
+        let _guard = self
+            .build_lock
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        if let Some(engine) = self.other_engine.get() {
+            return Ok(engine);
+        }
+        info!(decode_method = %mode, "Loading recognition engine");
+        let model = self.load_recognition_model()?;
+        let engine = new_engine(mode, self.beam_width, None, model)?;
+        Ok(self.other_engine.get_or_init(|| engine))
+    }
+
+    /// Load another copy of the recognition model from its local file,
+    /// checking that the file still has the digest it had when first loaded.
+    pub fn load_recognition_model(&self) -> Result<Model, anyhow::Error> {
+        let ModelFile { path, sha256, .. } = &self.recognition_model;
+        load_verified_model(path, sha256).with_context(|| {
+            format!(
+                "Failed to load text recognition model from {}",
+                path.display()
+            )
+        })
+    }
+
+    /// Return the text detection model file.
+    pub fn detection_model(&self) -> &ModelFile {
+        &self.detection_model
+    }
+
+    /// Return the text recognition model file.
+    pub fn recognition_model(&self) -> &ModelFile {
+        &self.recognition_model
+    }
+}
+
+/// Construct an engine that decodes recognition output using `mode`.
+fn new_engine(
+    mode: DecodeMode,
+    beam_width: u32,
+    detection_model: Option<Model>,
+    recognition_model: Model,
+) -> Result<OcrEngine, anyhow::Error> {
+    let decode_method = match mode {
+        DecodeMode::Greedy => DecodeMethod::Greedy,
+        DecodeMode::BeamSearch => DecodeMethod::BeamSearch { width: beam_width },
+    };
+    OcrEngine::new(OcrEngineParams {
+        detection_model,
+        recognition_model: Some(recognition_model),
+        debug: false,
+        decode_method,
+        ..Default::default()
+    })
+}
@@ -0,0 +1,270 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use rten_tensor::prelude::*;
+use rten_tensor::NdTensor;
+
+use crate::pipeline::{OcrOptions, OcrPage};
+
+/// Number of rows in the grid an image is reduced to for hashing.
+const HASH_ROWS: usize = 16;
+
+/// Number of columns in the grid. Each row yields `HASH_COLS - 1` bits.
+const HASH_COLS: usize = 17;
+
+/// Perceptual "difference hash" of an image.
+///
+/// The image is converted to grayscale and averaged down to a small grid.
+/// Each bit records whether a cell is darker than its right-hand neighbor,
+/// so images that look alike have hashes that differ in few bits, regardless
+/// of small changes in brightness or compression noise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameHash([u64; 4]);
+
+impl FrameHash {
+    /// Hash an HWC RGB image.
+    pub fn new(color_img: &NdTensor<u8, 3>) -> FrameHash {
+        let [height, width, chans] = color_img.shape();
+        let mut sums = [[0u64; HASH_COLS]; HASH_ROWS];
+        let mut counts = [[0u64; HASH_COLS]; HASH_ROWS];
+
+        let mut pixel = [0u8; 3];
+        for (i, value) in color_img.iter().enumerate() {
+            let chan = i % chans;
+            if chan < 3 {
+                pixel[chan] = *value;
+            }
+            if chan + 1 < chans {
+                continue;
+            }
+            let y = i / (chans * width);
+            let x = (i / chans) % width;
+            let [r, g, b] = pixel.map(u64::from);
+            let luma = (299 * r + 587 * g + 114 * b) / 1000;
+            let (row, col) = (y * HASH_ROWS / height, x * HASH_COLS / width);
+            sums[row][col] += luma;
+            counts[row][col] += 1;
+        }
+
+        let mut bits = [0u64; 4];
+        let mut bit = 0;
+        for (row_sums, row_counts) in sums.iter().zip(&counts) {
+            let means: Vec<u64> = row_sums
+                .iter()
+                .zip(row_counts)
+                .map(|(sum, count)| sum / count.max(&1))
+                .collect();
+            for pair in means.windows(2) {
+                if pair[0] < pair[1] {
+                    bits[bit / 64] |= 1 << (bit % 64);
+                }
+                bit += 1;
+            }
+        }
+        FrameHash(bits)
+    }
+
+    /// Return the number of bits that differ between two hashes.
+    pub fn distance(&self, other: &FrameHash) -> u32 {
+        self.0
+            .iter()
+            .zip(&other.0)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+}
+
+struct Entry {
+    hash: FrameHash,
+    shape: [usize; 3],
+    opts: OcrOptions,
+    page: OcrPage,
+}
+
+/// Cache of OCR results for recently processed frames, keyed by
+/// [FrameHash].
+///
+/// A frame whose hash is within `max_distance` bits of a cached frame with
+/// the same size and options reuses the cached result instead of running
+/// detection and recognition again.
+pub struct FrameCache {
+    capacity: usize,
+    max_distance: u32,
+
+    /// Entries in order of most recent use.
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+impl FrameCache {
+    /// Create a cache that holds up to `capacity` frames. A capacity of zero
+    /// disables the cache.
+    pub fn new(capacity: usize, max_distance: u32) -> FrameCache {
+        FrameCache {
+            capacity,
+            max_distance,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Return the cached result for a frame similar to `color_img`, or
+    /// compute it with `run` and cache it.
+    pub fn get_or_insert_with<E>(
+        &self,
+        color_img: &NdTensor<u8, 3>,
+        opts: &OcrOptions,
+        run: impl FnOnce() -> Result<OcrPage, E>,
+    ) -> Result<OcrPage, E> {
+        if self.capacity == 0 {
+            return run();
+        }
+
+        let hash = FrameHash::new(color_img);
+        let shape = color_img.shape();
+        {
+            let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+            let hit = entries.iter().position(|entry| {
+                entry.shape == shape
+                    && entry.opts == *opts
+                    && entry.hash.distance(&hash) <= self.max_distance
+            });
+            if let Some(index) = hit {
+                let entry = entries.remove(index).unwrap();
+                let page = entry.page.clone();
+                entries.push_front(entry);
+                return Ok(page);
+            }
+        }
+
+        // The lock is not held while running OCR, so concurrent requests for
+        // the same frame may both miss.
+        let page = run()?;
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries.push_front(Entry {
+            hash,
+            shape,
+            opts: opts.clone(),
+            page: page.clone(),
+        });
+        entries.truncate(self.capacity);
+        Ok(page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use rten_tensor::prelude::*;
+    use rten_tensor::NdTensor;
+
+    use super::{FrameCache, FrameHash};
+    use crate::pipeline::{OcrOptions, OcrPage};
+
+    /// Create an RGB image with a textured pattern.
+    fn image(width: usize, height: usize) -> NdTensor<u8, 3> {
+        NdTensor::from_fn([height, width, 3], |[y, x, c]| {
+            ((x * 37 + y * 11 + c * 5) % 251) as u8
+        })
+    }
+
+    fn page() -> OcrPage {
+        OcrPage {
+            lines: Vec::new(),
+            rotation: None,
+        }
+    }
+
+    #[test]
+    fn test_hash_distance() {
+        assert_eq!(FrameHash([0; 4]).distance(&FrameHash([0; 4])), 0);
+        assert_eq!(
+            FrameHash([0b101, 0, 0, 1 << 63]).distance(&FrameHash([0; 4])),
+            3
+        );
+        assert_eq!(FrameHash([u64::MAX; 4]).distance(&FrameHash([0; 4])), 256);
+    }
+
+    #[test]
+    fn test_hash_similar_images() {
+        let img = image(64, 48);
+        let hash = FrameHash::new(&img);
+        assert_eq!(FrameHash::new(&img.clone()), hash);
+
+        // A few changed pixels change few bits, if any.
+        let mut changed = img.clone();
+        for x in 0..4 {
+            changed[[10, x, 0]] = 255;
+        }
+        assert!(FrameHash::new(&changed).distance(&hash) <= 2);
+
+        // A mirrored image is very different.
+        let [height, width, _] = img.shape();
+        let mirrored =
+            NdTensor::from_fn([height, width, 3], |[y, x, c]| img[[y, width - 1 - x, c]]);
+        assert!(FrameHash::new(&mirrored).distance(&hash) > 64);
+    }
+
+    #[test]
+    fn test_cache_hits() {
+        let cache = FrameCache::new(2, 4);
+        let opts = OcrOptions::default();
+        let runs = Cell::new(0);
+        let run = || -> Result<OcrPage, ()> {
+            runs.set(runs.get() + 1);
+            Ok(page())
+        };
+
+        let img = image(64, 48);
+        cache.get_or_insert_with(&img, &opts, run).unwrap();
+        cache.get_or_insert_with(&img, &opts, run).unwrap();
+        assert_eq!(runs.get(), 1);
+
+        // A similar frame is a hit.
+        let mut similar = img.clone();
+        similar[[0, 0, 0]] = 255;
+        cache.get_or_insert_with(&similar, &opts, run).unwrap();
+        assert_eq!(runs.get(), 1);
+
+        // Different options or sizes are misses.
+        let other_opts = OcrOptions {
+            auto_rotate: true,
+            ..Default::default()
+        };
+        cache.get_or_insert_with(&img, &other_opts, run).unwrap();
+        assert_eq!(runs.get(), 2);
+        cache
+            .get_or_insert_with(&image(64, 47), &opts, run)
+            .unwrap();
+        assert_eq!(runs.get(), 3);
+
+        // The least recently used frame was evicted.
+        cache.get_or_insert_with(&img, &opts, run).unwrap();
+        assert_eq!(runs.get(), 4);
+    }
+
+    #[test]
+    fn test_cache_disabled_or_failed() {
+        let img = image(32, 32);
+        let opts = OcrOptions::default();
+
+        let cache = FrameCache::new(0, 4);
+        let runs = Cell::new(0);
+        for _ in 0..2 {
+            cache
+                .get_or_insert_with(&img, &opts, || -> Result<OcrPage, ()> {
+                    runs.set(runs.get() + 1);
+                    Ok(page())
+                })
+                .unwrap();
+        }
+        assert_eq!(runs.get(), 2);
+
+        // Failures are not cached.
+        let cache = FrameCache::new(2, 4);
+        assert!(cache.get_or_insert_with(&img, &opts, || Err(())).is_err());
+        assert!(cache
+            .get_or_insert_with(&img, &opts, || Ok::<_, ()>(page()))
+            .is_ok());
+        assert!(cache.get_or_insert_with(&img, &opts, || Err(())).is_ok());
+    }
+}
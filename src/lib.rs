@@ -0,0 +1,31 @@
+//! OCR pipeline behind the frame-ocr server.
+//!
+//! [OcrService] loads the text detection and recognition models and runs
+//! them on encoded images or RGB tensors, with optional preprocessing,
+//! regions of interest, automatic rotation and confidence scores. The
+//! results can be formatted with [OcrResult::format] in the same forms the
+//! server returns.
+
+pub mod confidence;
+pub mod decode;
+pub mod engine;
+pub mod frame_cache;
+pub mod markup;
+pub mod models;
+pub mod orientation;
+pub mod output;
+pub mod pipeline;
+pub mod preprocess;
+pub mod roi;
+pub mod sequence;
+pub mod service;
+pub mod timing;
+
+pub use decode::{decode_image, image_to_tensor, DecodeError, DecodedImage};
+pub use engine::{DecodeMode, EngineConfig};
+pub use models::{load_model, ModelSource};
+pub use output::{FormattedOutput, OcrOutput, OcrResult, OutputFormat};
+pub use pipeline::{OcrOptions, PipelineError};
+pub use preprocess::PreprocessStep;
+pub use roi::Roi;
+pub use service::{OcrError, OcrService};
@@ -0,0 +1,487 @@
+use std::fmt::{self, Write};
+
+use crate::output::{BoxOutput, LineOutput, OcrOutput};
+
+/// Software recorded as the producer of hOCR and ALTO documents.
+const SOFTWARE_NAME: &str = env!("CARGO_PKG_NAME");
+const SOFTWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Escape text for use in XML content and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Edges of a box as `[left, top, right, bottom]`, clipped to the page.
+///
+/// Text near the edge of an image can have a box that extends past it,
+/// which both formats disallow.
+fn page_edges(bbox: BoxOutput, output: &OcrOutput) -> [u32; 4] {
+    let (width, height) = (output.width as i32, output.height as i32);
+    [
+        bbox.x.clamp(0, width),
+        bbox.y.clamp(0, height),
+        (bbox.x + bbox.width).clamp(0, width),
+        (bbox.y + bbox.height).clamp(0, height),
+    ]
+    .map(|edge| edge as u32)
+}
+
+/// Return the smallest box that contains every line of a block.
+fn block_edges(lines: &[LineOutput], output: &OcrOutput) -> [u32; 4] {
+    lines
+        .iter()
+        .map(|line| page_edges(line.geometry.bounding_box, output))
+        .reduce(|[l1, t1, r1, b1], [l2, t2, r2, b2]| {
+            [l1.min(l2), t1.min(t2), r1.max(r2), b1.max(b2)]
+        })
+        .unwrap_or_default()
+}
+
+/// Split lines into blocks of consecutive lines from the same region of
+/// interest. Without regions, all lines form one block.
+fn blocks(lines: &[LineOutput]) -> impl Iterator<Item = &[LineOutput]> {
+    lines.chunk_by(|a, b| a.region == b.region)
+}
+
+/// A page of a multi-page hOCR or ALTO document.
+#[derive(Clone, Copy)]
+pub struct Page<'a> {
+    /// Name of the image the page was read from, eg. its file name.
+    pub image: Option<&'a str>,
+
+    pub output: &'a OcrOutput,
+}
+
+/// Render an OCR result as an hOCR document.
+///
+/// Each block of lines is an `ocr_carea` containing `ocr_line` and
+/// `ocrx_word` elements, with boxes in image pixels. Word confidences are
+/// reported as `x_wconf` percentages if they were scored.
+pub fn format_hocr(output: &OcrOutput) -> String {
+    format_hocr_pages(&[Page {
+        image: None,
+        output,
+    }])
+}
+
+/// Render the results for several images as one hOCR document, with an
+/// `ocr_page` for each. The image name of a page is recorded in its `image`
+/// property.
+pub fn format_hocr_pages(pages: &[Page]) -> String {
+    let mut doc = String::new();
+    write_hocr(&mut doc, pages).expect("Writing to a String cannot fail");
+    doc
+}
+
+fn write_hocr(out: &mut impl Write, pages: &[Page]) -> fmt::Result {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Transitional//EN" "http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd">"#
+    )?;
+    writeln!(out, r#"<html xmlns="http://www.w3.org/1999/xhtml">"#)?;
+    writeln!(out, "  <head>")?;
+    writeln!(out, "    <title></title>")?;
+    writeln!(
+        out,
+        r#"    <meta http-equiv="Content-Type" content="text/html; charset=utf-8"/>"#
+    )?;
+    writeln!(
+        out,
+        r#"    <meta name="ocr-system" content="{} {}"/>"#,
+        SOFTWARE_NAME, SOFTWARE_VERSION
+    )?;
+    writeln!(
+        out,
+        r#"    <meta name="ocr-capabilities" content="ocr_page ocr_carea ocr_line ocrx_word"/>"#
+    )?;
+    writeln!(out, "  </head>")?;
+    writeln!(out, "  <body>")?;
+    for (page_index, page) in pages.iter().enumerate() {
+        write_hocr_page(out, page_index + 1, page)?;
+    }
+    writeln!(out, "  </body>")?;
+    writeln!(out, "</html>")
+}
+
+fn write_hocr_page(out: &mut impl Write, page_id: usize, page: &Page) -> fmt::Result {
+    let output = page.output;
+    let image = page
+        .image
+        .map(|image| format!("image {}; ", escape(&format!("\"{}\"", image))))
+        .unwrap_or_default();
+    writeln!(
+        out,
+        r#"    <div class="ocr_page" id="page_{}" title="{}bbox 0 0 {} {}">"#,
+        page_id, image, output.width, output.height
+    )?;
+
+    let (mut line_id, mut word_id) = (0, 0);
+    for (block_index, lines) in blocks(&output.lines).enumerate() {
+        let [left, top, right, bottom] = block_edges(lines, output);
+        writeln!(
+            out,
+            r#"      <div class="ocr_carea" id="block_{}_{}" title="bbox {} {} {} {}">"#,
+            page_id,
+            block_index + 1,
+            left,
+            top,
+            right,
+            bottom
+        )?;
+        for line in lines {
+            line_id += 1;
+            let [left, top, right, bottom] = page_edges(line.geometry.bounding_box, output);
+            writeln!(
+                out,
+                r#"        <span class="ocr_line" id="line_{}_{}" title="bbox {} {} {} {}">"#,
+                page_id, line_id, left, top, right, bottom
+            )?;
+            for word in &line.words {
+                word_id += 1;
+                let [left, top, right, bottom] = page_edges(word.geometry.bounding_box, output);
+                let wconf = word
+                    .confidence
+                    .map(|c| format!("; x_wconf {}", (c * 100.).round().clamp(0., 100.)))
+                    .unwrap_or_default();
+                writeln!(
+                    out,
+                    r#"          <span class="ocrx_word" id="word_{}_{}" title="bbox {} {} {} {}{}">{}</span>"#,
+                    page_id,
+                    word_id,
+                    left,
+                    top,
+                    right,
+                    bottom,
+                    wconf,
+                    escape(&word.text)
+                )?;
+            }
+            writeln!(out, "        </span>")?;
+        }
+        writeln!(out, "      </div>")?;
+    }
+    writeln!(out, "    </div>")
+}
+
+/// Render an OCR result as an ALTO v4 XML document.
+///
+/// Each block of lines is a `TextBlock` containing `TextLine` and `String`
+/// elements, with positions in image pixels. Word confidences are reported
+/// in the `WC` attribute if they were scored.
+pub fn format_alto(output: &OcrOutput) -> String {
+    format_alto_pages(&[Page {
+        image: None,
+        output,
+    }])
+}
+
+/// Render the results for several images as one ALTO document, with a
+/// `Page` for each in the given order. ALTO has no place for the image name
+/// of a page, so it is not recorded.
+pub fn format_alto_pages(pages: &[Page]) -> String {
+    let mut doc = String::new();
+    write_alto(&mut doc, pages).expect("Writing to a String cannot fail");
+    doc
+}
+
+/// Format edges as the ALTO position attributes of an element.
+fn alto_position([left, top, right, bottom]: [u32; 4]) -> String {
+    format!(
+        r#"HPOS="{}" VPOS="{}" WIDTH="{}" HEIGHT="{}""#,
+        left,
+        top,
+        right - left,
+        bottom - top
+    )
+}
+
+fn write_alto(out: &mut impl Write, pages: &[Page]) -> fmt::Result {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<alto xmlns="http://www.loc.gov/standards/alto/ns-v4#" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.loc.gov/standards/alto/ns-v4# http://www.loc.gov/standards/alto/v4/alto-4-2.xsd">"#
+    )?;
+    writeln!(out, "  <Description>")?;
+    writeln!(out, "    <MeasurementUnit>pixel</MeasurementUnit>")?;
+    writeln!(out, r#"    <OCRProcessing ID="OCR_1">"#)?;
+    writeln!(out, "      <ocrProcessingStep>")?;
+    writeln!(out, "        <processingSoftware>")?;
+    writeln!(
+        out,
+        "          <softwareName>{}</softwareName>",
+        SOFTWARE_NAME
+    )?;
+    writeln!(
+        out,
+        "          <softwareVersion>{}</softwareVersion>",
+        SOFTWARE_VERSION
+    )?;
+    writeln!(out, "        </processingSoftware>")?;
+    writeln!(out, "      </ocrProcessingStep>")?;
+    writeln!(out, "    </OCRProcessing>")?;
+    writeln!(out, "  </Description>")?;
+    writeln!(out, "  <Layout>")?;
+    // IDs must be unique in the whole document, so they are numbered
+    // across pages.
+    let mut ids = AltoIds::default();
+    for (page_index, page) in pages.iter().enumerate() {
+        write_alto_page(out, page_index + 1, page.output, &mut ids)?;
+    }
+    writeln!(out, "  </Layout>")?;
+    writeln!(out, "</alto>")
+}
+
+/// Last IDs assigned to the elements of an ALTO document.
+#[derive(Default)]
+struct AltoIds {
+    block: usize,
+    line: usize,
+    word: usize,
+}
+
+fn write_alto_page(
+    out: &mut impl Write,
+    page_id: usize,
+    output: &OcrOutput,
+    ids: &mut AltoIds,
+) -> fmt::Result {
+    writeln!(
+        out,
+        r#"    <Page ID="PAGE_{}" PHYSICAL_IMG_NR="{}" WIDTH="{}" HEIGHT="{}">"#,
+        page_id, page_id, output.width, output.height
+    )?;
+    writeln!(
+        out,
+        r#"      <PrintSpace {}>"#,
+        alto_position([0, 0, output.width, output.height])
+    )?;
+
+    for lines in blocks(&output.lines) {
+        ids.block += 1;
+        writeln!(
+            out,
+            r#"        <TextBlock ID="BLOCK_{}" {}>"#,
+            ids.block,
+            alto_position(block_edges(lines, output))
+        )?;
+        for line in lines {
+            ids.line += 1;
+            writeln!(
+                out,
+                r#"          <TextLine ID="LINE_{}" {}>"#,
+                ids.line,
+                alto_position(page_edges(line.geometry.bounding_box, output))
+            )?;
+            for (index, word) in line.words.iter().enumerate() {
+                ids.word += 1;
+                if index > 0 {
+                    writeln!(out, "            <SP/>")?;
+                }
+                let wc = word
+                    .confidence
+                    .map(|c| format!(r#" WC="{:.3}""#, c.clamp(0., 1.)))
+                    .unwrap_or_default();
+                writeln!(
+                    out,
+                    r#"            <String ID="STRING_{}" {} CONTENT="{}"{}/>"#,
+                    ids.word,
+                    alto_position(page_edges(word.geometry.bounding_box, output)),
+                    escape(&word.text),
+                    wc
+                )?;
+            }
+            writeln!(out, "          </TextLine>")?;
+        }
+        writeln!(out, "        </TextBlock>")?;
+    }
+
+    writeln!(out, "      </PrintSpace>")?;
+    writeln!(out, "    </Page>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, format_alto, format_alto_pages, format_hocr_pages, Page};
+    use crate::output::{
+        BoxOutput, GeometryOutput, LineOutput, OcrOutput, PointOutput, WordOutput,
+    };
+
+    fn geometry([x, y, width, height]: [i32; 4]) -> GeometryOutput {
+        let point = |x: i32, y: i32| PointOutput {
+            x: x as f32,
+            y: y as f32,
+        };
+        GeometryOutput {
+            rotated_rect: [
+                point(x, y),
+                point(x + width, y),
+                point(x + width, y + height),
+                point(x, y + height),
+            ],
+            bounding_box: BoxOutput {
+                x,
+                y,
+                width,
+                height,
+            },
+        }
+    }
+
+    fn word(text: &str, bbox: [i32; 4], confidence: Option<f32>) -> WordOutput {
+        WordOutput {
+            text: text.to_string(),
+            geometry: geometry(bbox),
+            confidence,
+            chars: None,
+        }
+    }
+
+    fn line(words: Vec<WordOutput>, bbox: [i32; 4], region: Option<&str>) -> LineOutput {
+        let text: Vec<&str> = words.iter().map(|w| w.text.as_str()).collect();
+        LineOutput {
+            text: text.join(" "),
+            geometry: geometry(bbox),
+            confidence: None,
+            region: region.map(str::to_string),
+            words,
+        }
+    }
+
+    fn output(lines: Vec<LineOutput>) -> OcrOutput {
+        OcrOutput {
+            width: 200,
+            height: 100,
+            exif_orientation: None,
+            rotation: None,
+            lines,
+        }
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("plain text"), "plain text");
+        assert_eq!(
+            escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+        assert_eq!(escape("naïve café"), "naïve café");
+    }
+
+    #[test]
+    fn test_format_alto() {
+        let output = output(vec![line(
+            vec![
+                word("Fish", [10, 20, 40, 15], Some(0.9)),
+                word("&", [55, 20, 10, 15], None),
+                // Extends past the right edge of the image.
+                word("Chips", [180, 20, 40, 15], Some(1.2)),
+            ],
+            [10, 20, 210, 15],
+            None,
+        )]);
+        let doc = format_alto(&output);
+
+        assert!(doc.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?>"#));
+        assert!(doc.contains("<MeasurementUnit>pixel</MeasurementUnit>"));
+        let layout = doc
+            .split_once("  <Layout>\n")
+            .and_then(|(_, rest)| rest.split_once("  </Layout>\n"))
+            .map(|(layout, _)| layout)
+            .unwrap();
+        assert_eq!(
+            layout,
+            r#"    <Page ID="PAGE_1" PHYSICAL_IMG_NR="1" WIDTH="200" HEIGHT="100">
+      <PrintSpace HPOS="0" VPOS="0" WIDTH="200" HEIGHT="100">
+        <TextBlock ID="BLOCK_1" HPOS="10" VPOS="20" WIDTH="190" HEIGHT="15">
+          <TextLine ID="LINE_1" HPOS="10" VPOS="20" WIDTH="190" HEIGHT="15">
+            <String ID="STRING_1" HPOS="10" VPOS="20" WIDTH="40" HEIGHT="15" CONTENT="Fish" WC="0.900"/>
+            <SP/>
+            <String ID="STRING_2" HPOS="55" VPOS="20" WIDTH="10" HEIGHT="15" CONTENT="&amp;"/>
+            <SP/>
+            <String ID="STRING_3" HPOS="180" VPOS="20" WIDTH="20" HEIGHT="15" CONTENT="Chips" WC="1.000"/>
+          </TextLine>
+        </TextBlock>
+      </PrintSpace>
+    </Page>
+"#
+        );
+        assert!(doc.ends_with("</alto>\n"));
+    }
+
+    #[test]
+    fn test_format_alto_pages() {
+        // Lines from different regions are in separate blocks, and IDs are
+        // numbered across pages.
+        let first = output(vec![
+            line(
+                vec![word("one", [0, 0, 30, 10], None)],
+                [0, 0, 30, 10],
+                None,
+            ),
+            line(
+                vec![word("two", [0, 50, 30, 10], None)],
+                [0, 50, 30, 10],
+                Some("ticker"),
+            ),
+        ]);
+        let second = output(vec![line(
+            vec![word("three", [0, 0, 50, 10], None)],
+            [0, 0, 50, 10],
+            None,
+        )]);
+        let doc = format_alto_pages(&[
+            Page {
+                image: Some("a.png"),
+                output: &first,
+            },
+            Page {
+                image: Some("b.png"),
+                output: &second,
+            },
+        ]);
+
+        for id in [
+            r#"<Page ID="PAGE_1""#,
+            r#"<Page ID="PAGE_2""#,
+            r#"<TextBlock ID="BLOCK_2""#,
+            r#"<TextBlock ID="BLOCK_3""#,
+            r#"<String ID="STRING_3" HPOS="0" VPOS="0" WIDTH="50" HEIGHT="10" CONTENT="three"/>"#,
+        ] {
+            assert!(doc.contains(id), "missing {}", id);
+        }
+        assert_eq!(doc.matches("<TextBlock").count(), 3);
+        assert!(!doc.contains("a.png"));
+    }
+
+    #[test]
+    fn test_format_hocr_pages() {
+        let output = output(vec![line(
+            vec![word("<hi>", [10, 20, 40, 15], Some(0.874))],
+            [10, 20, 40, 15],
+            None,
+        )]);
+        let doc = format_hocr_pages(&[Page {
+            image: Some("frame \"1\".png"),
+            output: &output,
+        }]);
+
+        assert!(doc.contains(
+            r#"<div class="ocr_page" id="page_1" title="image &quot;frame &quot;1&quot;.png&quot;; bbox 0 0 200 100">"#
+        ));
+        assert!(doc.contains(
+            r#"<span class="ocrx_word" id="word_1_1" title="bbox 10 20 50 35; x_wconf 87">&lt;hi&gt;</span>"#
+        ));
+    }
+}
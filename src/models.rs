@@ -1,15 +1,46 @@
 use std::fmt;
-use std::path::PathBuf;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
 #[cfg(not(target_arch = "wasm32"))]
-use std::{fs, path::Path};
+use std::io::{Read, Write};
 
 use anyhow::anyhow;
 use rten::Model;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
 
+#[cfg(not(target_arch = "wasm32"))]
+use tempfile::NamedTempFile;
 #[cfg(not(target_arch = "wasm32"))]
 use url::Url;
 
+/// URL of the published text detection model.
+pub const DETECTION_MODEL: &str =
+    "https://ocrs-models.s3-accelerate.amazonaws.com/text-detection.rten";
+
+/// URL of the published text recognition model.
+pub const RECOGNITION_MODEL: &str =
+    "https://ocrs-models.s3-accelerate.amazonaws.com/text-recognition.rten";
+
+/// Expected SHA-256 digest of [DETECTION_MODEL], used when no digest is
+/// configured. `None` until the digest of the published file is pinned.
+pub const DETECTION_MODEL_SHA256: Option<&str> = None;
+
+/// Expected SHA-256 digest of [RECOGNITION_MODEL], used when no digest is
+/// configured. `None` until the digest of the published file is pinned.
+pub const RECOGNITION_MODEL_SHA256: Option<&str> = None;
+
+/// Return the built-in SHA-256 digest of a published model, if `url` is one.
+pub fn published_sha256(url: &str) -> Option<&'static str> {
+    match url {
+        DETECTION_MODEL => DETECTION_MODEL_SHA256,
+        RECOGNITION_MODEL => RECOGNITION_MODEL_SHA256,
+        _ => None,
+    }
+}
+
 /// Return the path to the directory in which cached models etc. should be
 /// saved.
 #[cfg(not(target_arch = "wasm32"))]
@@ -37,65 +68,521 @@ fn filename_from_url(url: &str) -> Option<String> {
         .map(|s| s.to_string())
 }
 
+/// Compute the hex-encoded SHA-256 digest of a file.
+pub fn file_sha256(path: &Path) -> Result<String, anyhow::Error> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hex_digest(hasher))
+}
+
+fn hex_digest(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Check that `actual`, the SHA-256 digest of `name`, matches `expected` if
+/// given.
+fn check_sha256(
+    name: impl fmt::Display,
+    actual: &str,
+    expected: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    match expected {
+        Some(expected) if !actual.eq_ignore_ascii_case(expected.trim()) => Err(anyhow!(
+            "Checksum mismatch for {}: expected SHA-256 {}, got {}",
+            name,
+            expected.trim(),
+            actual
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Check that the file at `path` has the SHA-256 digest `expected`.
+pub fn verify_sha256(path: &Path, expected: &str) -> Result<(), anyhow::Error> {
+    check_sha256(path.display(), &file_sha256(path)?, Some(expected))
+}
+
+/// Load a model from a local file, checking that the file still has the
+/// SHA-256 digest `expected`.
+///
+/// The file is read once and the model loaded from the bytes that were
+/// checked, so a file replaced after it was first verified is never loaded.
+pub fn load_verified_model(path: &Path, expected: &str) -> Result<Model, anyhow::Error> {
+    let data = fs::read(path)?;
+    let mut hasher = Sha256::new();
+    hasher.update(&data);
+    check_sha256(path.display(), &hex_digest(hasher), Some(expected))?;
+    Ok(Model::load(data)?)
+}
+
+/// Check that a local model file exists and matches `expected_sha256`, if
+/// given, and return its digest.
+fn verify_local_file(path: &Path, expected_sha256: Option<&str>) -> Result<String, anyhow::Error> {
+    if !path.is_file() {
+        return Err(anyhow!("Model file {} does not exist", path.display()));
+    }
+    let digest = file_sha256(path)?;
+    check_sha256(path.display(), &digest, expected_sha256)?;
+    Ok(digest)
+}
+
+/// Return the path of the file that records the digest of a cached download.
+#[cfg(not(target_arch = "wasm32"))]
+fn digest_path(file_path: &Path) -> PathBuf {
+    let mut path = file_path.as_os_str().to_owned();
+    path.push(".sha256");
+    PathBuf::from(path)
+}
+
+/// Check a previously downloaded file against `expected_sha256`, or if that is
+/// not given, against the digest recorded when the file was downloaded, and
+/// return its digest.
+///
+/// Files cached by older versions have no recorded digest and are accepted
+/// as-is. [load_model] re-downloads them if they fail to load.
+#[cfg(not(target_arch = "wasm32"))]
+fn verify_cached_file(
+    file_path: &Path,
+    expected_sha256: Option<&str>,
+) -> Result<String, anyhow::Error> {
+    let recorded = match expected_sha256 {
+        Some(_) => None,
+        None => match fs::read_to_string(digest_path(file_path)) {
+            Ok(digest) => Some(digest),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        },
+    };
+    let digest = file_sha256(file_path)?;
+    check_sha256(
+        file_path.display(),
+        &digest,
+        expected_sha256.or(recorded.as_deref()),
+    )?;
+    Ok(digest)
+}
+
+/// Write `contents` to `path` by writing a temporary file in the same
+/// directory and renaming it, so that readers never see a partial file.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), anyhow::Error> {
+    let dir = path
+        .parent()
+        .ok_or(anyhow!("Invalid path {}", path.display()))?;
+    let mut tmp_file = NamedTempFile::new_in(dir)?;
+    tmp_file.write_all(contents)?;
+    tmp_file.as_file().sync_all()?;
+    tmp_file.persist(path)?;
+    Ok(())
+}
+
 /// Download a file from `url` to a local cache, if not already fetched, and
-/// return the path to the local file.
+/// return the path to the local file and its SHA-256 digest.
+///
+/// The download is verified against `expected_sha256`, if given, and must
+/// load as a model. It is only moved into place, and its digest recorded for
+/// later checks, once both checks pass. A cached file that fails
+/// verification is downloaded again. If no digest is given, the built-in
+/// digest of a published model is used.
+#[cfg(not(target_arch = "wasm32"))]
+fn download_file(
+    url: &str,
+    filename: Option<&str>,
+    expected_sha256: Option<&str>,
+) -> Result<(PathBuf, String), anyhow::Error> {
+    let expected_sha256 = expected_sha256.or_else(|| published_sha256(url));
+    download_file_to(&cache_dir()?, url, filename, expected_sha256)
+}
+
+/// Download a file like [download_file], using `cache_dir` as the cache.
 #[cfg(not(target_arch = "wasm32"))]
-fn download_file(url: &str, filename: Option<&str>) -> Result<PathBuf, anyhow::Error> {
-    let cache_dir = cache_dir()?;
+fn download_file_to(
+    cache_dir: &Path,
+    url: &str,
+    filename: Option<&str>,
+    expected_sha256: Option<&str>,
+) -> Result<(PathBuf, String), anyhow::Error> {
     let filename = match filename {
         Some(fname) => fname.to_string(),
         None => filename_from_url(url).ok_or(anyhow!("Could not get destination filename"))?,
     };
     let file_path = cache_dir.join(filename);
     if file_path.exists() {
-        return Ok(file_path);
+        match verify_cached_file(&file_path, expected_sha256) {
+            Ok(digest) => return Ok((file_path, digest)),
+            Err(err) => warn!("{}. Downloading again.", err),
+        }
     }
 
-    eprintln!("Downloading {}...", url);
+    info!(url, "Downloading model");
+    if expected_sha256.is_none() {
+        warn!(
+            url,
+            "No SHA-256 digest is configured for this model, so the download cannot be verified"
+        );
+    }
 
     let mut reader = ureq::get(url).call()?.into_reader();
-    let mut body = Vec::new();
-    reader.read_to_end(&mut body)?;
+    let mut tmp_file = NamedTempFile::new_in(cache_dir)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        tmp_file.write_all(&buf[..n])?;
+    }
+    let digest = hex_digest(hasher);
+    check_sha256(url, &digest, expected_sha256)?;
 
-    fs::write(&file_path, &body)?;
+    // Without an expected digest, this is the only check that the download
+    // is a model rather than eg. a truncated file or an error page.
+    tmp_file.as_file().sync_all()?;
+    Model::load_file(tmp_file.path())
+        .map_err(|err| anyhow!("Downloaded file from {} is not a valid model: {}", url, err))?;
+    tmp_file.persist(&file_path)?;
+    write_atomic(&digest_path(&file_path), digest.as_bytes())?;
 
-    Ok(file_path)
+    Ok((file_path, digest))
 }
 
 #[cfg(target_arch = "wasm32")]
-fn download_file(_url: &str, _filename: Option<&str>) -> Result<PathBuf, anyhow::Error> {
+fn download_file(
+    _url: &str,
+    _filename: Option<&str>,
+    _expected_sha256: Option<&str>,
+) -> Result<(PathBuf, String), anyhow::Error> {
     Err(anyhow!(
         "Downloading models from a URL is not supported on the current platform"
     ))
 }
 
 /// Location that a model can be loaded from.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum ModelSource<'a> {
     /// Load model from an HTTP(S) URL.
     Url(&'a str),
+
+    /// Load model from a local file.
+    File(&'a Path),
 }
 
 impl<'a> fmt::Display for ModelSource<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                ModelSource::Url(url) => url
-            }
-        )
+        match self {
+            ModelSource::Url(url) => write!(f, "{}", url),
+            ModelSource::File(path) => write!(f, "{}", path.display()),
+        }
     }
 }
 
+/// A model and the local file it was loaded from.
+pub struct LoadedModel {
+    pub model: Model,
+
+    /// Path of the verified local file. Further copies of the model can be
+    /// loaded from it with [Model::load_file] without fetching or verifying
+    /// it again.
+    pub path: PathBuf,
+
+    /// Hex-encoded SHA-256 digest of the file.
+    pub sha256: String,
+}
+
 /// Load a model from a given source.
 ///
 /// If the source is a URL, the model will be downloaded and cached locally if
-/// needed.
-pub fn load_model(source: ModelSource) -> Result<Model, anyhow::Error> {
-    let model_path = match source {
-        ModelSource::Url(url) => download_file(url, None)?,
+/// needed. If the source is a file, it is never fetched from the network.
+///
+/// If `expected_sha256` is given, the model file must have that SHA-256
+/// digest. Otherwise the published models are checked against their
+/// built-in digests. A cached download that fails verification or fails to
+/// load is downloaded again.
+pub fn load_model(
+    source: ModelSource,
+    expected_sha256: Option<&str>,
+) -> Result<Model, anyhow::Error> {
+    load_model_file(source, expected_sha256).map(|loaded| loaded.model)
+}
+
+/// Load a model like [load_model], and also return the local file it was
+/// loaded from.
+pub fn load_model_file(
+    source: ModelSource,
+    expected_sha256: Option<&str>,
+) -> Result<LoadedModel, anyhow::Error> {
+    let (path, sha256) = fetch_model(source, expected_sha256)?;
+    match (Model::load_file(&path), source) {
+        (Ok(model), _) => Ok(LoadedModel {
+            model,
+            path,
+            sha256,
+        }),
+        (Err(err), ModelSource::Url(url)) => {
+            warn!(
+                "Failed to load cached model {}: {}. Downloading again.",
+                path.display(),
+                err
+            );
+            fs::remove_file(&path)?;
+            let (path, sha256) = download_file(url, None, expected_sha256)?;
+            let model = Model::load_file(&path)?;
+            Ok(LoadedModel {
+                model,
+                path,
+                sha256,
+            })
+        }
+        (Err(err), ModelSource::File(_)) => Err(err.into()),
+    }
+}
+
+/// Fetch a model, if it is not already available locally, and return the
+/// path of the local file and its SHA-256 digest.
+///
+/// This does the same checks as [load_model], except that the model is not
+/// loaded.
+pub fn fetch_model(
+    source: ModelSource,
+    expected_sha256: Option<&str>,
+) -> Result<(PathBuf, String), anyhow::Error> {
+    match source {
+        ModelSource::Url(url) => download_file(url, None, expected_sha256),
+        ModelSource::File(path) => {
+            let digest = verify_local_file(path, expected_sha256)?;
+            Ok((path.to_path_buf(), digest))
+        }
+    }
+}
+
+/// Check that a model is available locally, matches its expected digest and
+/// can be loaded, without downloading it.
+///
+/// A downloaded model is checked against `expected_sha256`, or if that is
+/// not given, against the built-in digest of a published model or the digest
+/// recorded when it was downloaded. Returns
+/// the path of the local file and its SHA-256 digest.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn verify_model(
+    source: ModelSource,
+    expected_sha256: Option<&str>,
+) -> Result<(PathBuf, String), anyhow::Error> {
+    let (path, digest) = match source {
+        ModelSource::Url(url) => {
+            let filename =
+                filename_from_url(url).ok_or(anyhow!("Could not get destination filename"))?;
+            let path = cache_dir()?.join(filename);
+            if !path.is_file() {
+                return Err(anyhow!("Model {} has not been downloaded", url));
+            }
+            let expected_sha256 = expected_sha256.or_else(|| published_sha256(url));
+            let digest = verify_cached_file(&path, expected_sha256)?;
+            (path, digest)
+        }
+        ModelSource::File(path) => {
+            let digest = verify_local_file(path, expected_sha256)?;
+            (path.to_path_buf(), digest)
+        }
+    };
+    Model::load_file(&path)
+        .map_err(|err| anyhow!("Failed to load model {}: {}", path.display(), err))?;
+    Ok((path, digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use rten::model_builder::{ModelBuilder, ModelFormat, OpType};
+    use rten::Dimension;
+    use rten_tensor::prelude::*;
+    use rten_tensor::Tensor;
+
+    use super::{
+        digest_path, download_file_to, file_sha256, load_verified_model, published_sha256,
+        verify_sha256, DETECTION_MODEL, DETECTION_MODEL_SHA256, RECOGNITION_MODEL,
+        RECOGNITION_MODEL_SHA256,
     };
-    let model = Model::load_file(model_path)?;
-    Ok(model)
+
+    /// SHA-256 digest of "hello world".
+    const HELLO_SHA256: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    /// Build a minimal model which adds a constant to its input.
+    fn tiny_model() -> Vec<u8> {
+        let mut mb = ModelBuilder::new(ModelFormat::V1);
+        let mut gb = mb.graph_builder();
+        let input_id = gb.add_value("input", Some(&[Dimension::Fixed(1)]));
+        gb.add_input(input_id);
+        let output_id = gb.add_value("output", None);
+        gb.add_output(output_id);
+        let bias = Tensor::from_scalar(0.5f32);
+        let bias_id = gb.add_constant(bias.view());
+        gb.add_operator(
+            "add",
+            OpType::Add,
+            &[Some(input_id), Some(bias_id)],
+            &[output_id],
+        );
+        let graph = gb.finish();
+        mb.set_graph(graph);
+        mb.finish()
+    }
+
+    /// Serve `body` over HTTP on a local port. Returns the URL of the file
+    /// and a count of the requests received.
+    fn serve(body: Vec<u8>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/model.rten", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn sha256_of(contents: &[u8]) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, contents).unwrap();
+        file_sha256(&path).unwrap()
+    }
+
+    #[test]
+    fn test_verify_sha256() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hello.txt");
+        fs::write(&path, "hello world").unwrap();
+
+        assert_eq!(file_sha256(&path).unwrap(), HELLO_SHA256);
+        assert!(verify_sha256(&path, HELLO_SHA256).is_ok());
+        assert!(verify_sha256(&path, &HELLO_SHA256.to_uppercase()).is_ok());
+        assert!(verify_sha256(&path, &format!(" {}\n", HELLO_SHA256)).is_ok());
+
+        let err = verify_sha256(&path, &"0".repeat(64)).unwrap_err();
+        assert!(err.to_string().contains("Checksum mismatch"));
+
+        assert!(verify_sha256(&dir.path().join("missing.txt"), HELLO_SHA256).is_err());
+    }
+
+    #[test]
+    fn test_load_verified_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.rten");
+        fs::write(&path, tiny_model()).unwrap();
+        let digest = file_sha256(&path).unwrap();
+        assert!(load_verified_model(&path, &digest).is_ok());
+
+        // The file was replaced after it was verified.
+        let mut other_model = tiny_model();
+        other_model.extend_from_slice(&[0; 8]);
+        fs::write(&path, other_model).unwrap();
+        let err = load_verified_model(&path, &digest).err().unwrap();
+        assert!(err.to_string().contains("Checksum mismatch"));
+    }
+
+    #[test]
+    fn test_published_sha256() {
+        assert_eq!(published_sha256(DETECTION_MODEL), DETECTION_MODEL_SHA256);
+        assert_eq!(
+            published_sha256(RECOGNITION_MODEL),
+            RECOGNITION_MODEL_SHA256
+        );
+        assert_eq!(published_sha256("https://example.com/model.rten"), None);
+    }
+
+    #[test]
+    fn test_download_file_caches_and_records_digest() {
+        let model = tiny_model();
+        let expected = sha256_of(&model);
+        let (url, requests) = serve(model.clone());
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        let (path, digest) = download_file_to(cache_dir.path(), &url, None, None).unwrap();
+        assert_eq!(path, cache_dir.path().join("model.rten"));
+        assert_eq!(digest, expected);
+        assert_eq!(fs::read(&path).unwrap(), model);
+        assert_eq!(fs::read_to_string(digest_path(&path)).unwrap(), expected);
+
+        // A verified cached file is not downloaded again.
+        let (_, digest) = download_file_to(cache_dir.path(), &url, None, None).unwrap();
+        assert_eq!(digest, expected);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_download_file_replaces_corrupt_cached_file() {
+        let model = tiny_model();
+        let expected = sha256_of(&model);
+        let (url, requests) = serve(model.clone());
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        let (path, _) = download_file_to(cache_dir.path(), &url, None, None).unwrap();
+        fs::write(&path, b"corrupted").unwrap();
+
+        // The cached file no longer matches its recorded digest.
+        let (path, digest) = download_file_to(cache_dir.path(), &url, None, None).unwrap();
+        assert_eq!(digest, expected);
+        assert_eq!(fs::read(&path).unwrap(), model);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // Or its expected digest.
+        fs::write(&path, b"corrupted").unwrap();
+        download_file_to(cache_dir.path(), &url, None, Some(&expected)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), model);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_download_file_rejects_bad_download() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cached_path = cache_dir.path().join("model.rten");
+        let no_files = |dir: &Path| fs::read_dir(dir).unwrap().next().is_none();
+
+        // Digest mismatch.
+        let (url, _) = serve(tiny_model());
+        let err =
+            download_file_to(cache_dir.path(), &url, None, Some(&"0".repeat(64))).unwrap_err();
+        assert!(err.to_string().contains("Checksum mismatch"));
+        assert!(!cached_path.exists());
+        assert!(no_files(cache_dir.path()));
+
+        // Not a model, eg. an error page.
+        let (url, _) = serve(b"<html>Not found</html>".to_vec());
+        let err = download_file_to(cache_dir.path(), &url, None, None).unwrap_err();
+        assert!(err.to_string().contains("is not a valid model"));
+        assert!(no_files(cache_dir.path()));
+    }
 }
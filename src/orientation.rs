@@ -0,0 +1,267 @@
+use rten_imageproc::{Rect, RotatedRect};
+use rten_tensor::prelude::*;
+use rten_tensor::NdTensor;
+use serde::Serialize;
+
+/// Skew angles smaller than this, in degrees, are not corrected.
+const MIN_SKEW: f32 = 0.5;
+
+/// Skew angles larger than this, in degrees, are assumed to be misdetections
+/// rather than skewed text.
+const MAX_SKEW: f32 = 20.;
+
+/// Minimum number of words needed to estimate skew.
+const MIN_SKEW_WORDS: usize = 3;
+
+/// Rotation applied to an image so that its text is upright.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Rotation {
+    /// Clockwise rotation in degrees: 0, 90, 180 or 270.
+    pub orientation: u32,
+
+    /// Further clockwise rotation in degrees applied to straighten skewed
+    /// text.
+    pub deskew: f32,
+}
+
+/// Rotate an HWC image clockwise by `quarter_turns` * 90 degrees.
+pub fn rotate_quarter_turns(img: &NdTensor<u8, 3>, quarter_turns: u32) -> NdTensor<u8, 3> {
+    let [height, width, chans] = img.shape();
+    let turns = quarter_turns % 4;
+    let (out_height, out_width) = if turns % 2 == 1 {
+        (width, height)
+    } else {
+        (height, width)
+    };
+    NdTensor::from_fn([out_height, out_width, chans], |[y, x, c]| {
+        let (src_y, src_x) = match turns {
+            0 => (y, x),
+            1 => (height - 1 - x, y),
+            2 => (height - 1 - y, width - 1 - x),
+            _ => (x, width - 1 - y),
+        };
+        img[[src_y, src_x, c]]
+    })
+}
+
+/// Map a rectangle in an image rotated by [rotate_quarter_turns] back to the
+/// original image of size `width` x `height`.
+pub fn unrotate_quarter_turns(rect: Rect, quarter_turns: u32, width: i32, height: i32) -> Rect {
+    let map = |x: i32, y: i32| match quarter_turns % 4 {
+        0 => (x, y),
+        1 => (y, height - x),
+        2 => (width - x, height - y),
+        _ => (width - y, x),
+    };
+    let (x0, y0) = map(rect.left(), rect.top());
+    let (x1, y1) = map(rect.right(), rect.bottom());
+    Rect::from_tlbr(y0.min(y1), x0.min(x1), y0.max(y1), x0.max(x1))
+}
+
+/// Estimate the angle, in degrees clockwise from horizontal, of the text in
+/// an image from the oriented rectangles of its words.
+///
+/// Returns `None` if there are too few words to tell or the text is straight
+/// enough already.
+pub fn estimate_skew(word_rects: &[RotatedRect]) -> Option<f32> {
+    // Only use words that are clearly wider than they are tall, so that the
+    // direction of the baseline is unambiguous.
+    let mut angles: Vec<f32> = word_rects
+        .iter()
+        .filter(|rect| rect.width() > rect.height() * 1.5)
+        .map(|rect| {
+            let baseline = rect.up_axis().perpendicular();
+            let mut angle = baseline.y.atan2(baseline.x).to_degrees();
+            // The baseline may point either way. Normalize to (-90, 90].
+            if angle > 90. {
+                angle -= 180.;
+            } else if angle <= -90. {
+                angle += 180.;
+            }
+            angle
+        })
+        .collect();
+    if angles.len() < MIN_SKEW_WORDS {
+        return None;
+    }
+    angles.sort_by(f32::total_cmp);
+    let median = angles[angles.len() / 2];
+    (median.abs() >= MIN_SKEW && median.abs() <= MAX_SKEW).then_some(median)
+}
+
+/// Rotation of an image by an arbitrary angle about its center, onto a
+/// canvas large enough to hold the whole rotated image.
+pub struct FreeRotation {
+    cos: f32,
+    sin: f32,
+    src_center: (f32, f32),
+    dst_center: (f32, f32),
+}
+
+impl FreeRotation {
+    /// Rotate an HWC image clockwise by `degrees`, filling the corners by
+    /// extending the image's edges.
+    pub fn apply(img: &NdTensor<u8, 3>, degrees: f32) -> (NdTensor<u8, 3>, FreeRotation) {
+        let [height, width, chans] = img.shape();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let out_width = (width as f32 * cos.abs() + height as f32 * sin.abs()).ceil() as usize;
+        let out_height = (width as f32 * sin.abs() + height as f32 * cos.abs()).ceil() as usize;
+        let rotation = FreeRotation {
+            cos,
+            sin,
+            src_center: (width as f32 / 2., height as f32 / 2.),
+            dst_center: (out_width as f32 / 2., out_height as f32 / 2.),
+        };
+
+        let mut out = NdTensor::zeros([out_height, out_width, chans]);
+        for y in 0..out_height {
+            for x in 0..out_width {
+                // Sample the source with bilinear interpolation.
+                let (sx, sy) = rotation.unmap_point(x as f32 + 0.5, y as f32 + 0.5);
+                let sx = (sx - 0.5).clamp(0., (width - 1) as f32);
+                let sy = (sy - 0.5).clamp(0., (height - 1) as f32);
+                let (x0, y0) = (sx.floor() as usize, sy.floor() as usize);
+                let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+                let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+                for c in 0..chans {
+                    let top = img[[y0, x0, c]] as f32 * (1. - fx) + img[[y0, x1, c]] as f32 * fx;
+                    let bottom = img[[y1, x0, c]] as f32 * (1. - fx) + img[[y1, x1, c]] as f32 * fx;
+                    out[[y, x, c]] = (top * (1. - fy) + bottom * fy).round() as u8;
+                }
+            }
+        }
+        (out, rotation)
+    }
+
+    /// Map a point in the rotated image to the original image.
+    fn unmap_point(&self, x: f32, y: f32) -> (f32, f32) {
+        let (dx, dy) = (x - self.dst_center.0, y - self.dst_center.1);
+        (
+            self.src_center.0 + dx * self.cos + dy * self.sin,
+            self.src_center.1 - dx * self.sin + dy * self.cos,
+        )
+    }
+
+    /// Map a rectangle in the rotated image to the bounding box of the
+    /// corresponding area in the original image.
+    pub fn unmap_rect(&self, rect: Rect) -> Rect {
+        let corners = rect
+            .corners()
+            .map(|p| self.unmap_point(p.x as f32, p.y as f32));
+        let xs = corners.map(|(x, _)| x);
+        let ys = corners.map(|(_, y)| y);
+        let min = |vs: [f32; 4]| vs.into_iter().fold(f32::INFINITY, f32::min).round() as i32;
+        let max = |vs: [f32; 4]| vs.into_iter().fold(f32::NEG_INFINITY, f32::max).round() as i32;
+        Rect::from_tlbr(min(ys), min(xs), max(ys), max(xs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_imageproc::Rect;
+    use rten_tensor::prelude::*;
+    use rten_tensor::NdTensor;
+
+    use super::{rotate_quarter_turns, unrotate_quarter_turns, FreeRotation};
+
+    /// Return a single-channel image of the given size.
+    fn image(height: usize, width: usize, pixels: &[u8]) -> NdTensor<u8, 3> {
+        NdTensor::from_data([height, width, 1], pixels.to_vec())
+    }
+
+    /// Return the bounding box of the pixels brighter than 128.
+    fn bright_rect(img: &NdTensor<u8, 3>) -> Rect {
+        let [height, width, _] = img.shape();
+        let mut rect: Option<Rect> = None;
+        for y in 0..height {
+            for x in 0..width {
+                if img[[y, x, 0]] > 128 {
+                    let pixel = Rect::from_tlbr(y as i32, x as i32, y as i32 + 1, x as i32 + 1);
+                    rect = Some(rect.map_or(pixel, |r| r.union(pixel)));
+                }
+            }
+        }
+        rect.expect("image has no bright pixels")
+    }
+
+    #[test]
+    fn test_rotate_quarter_turns() {
+        let img = image(2, 3, &[0, 1, 2, 3, 4, 5]);
+
+        for (turns, shape, pixels) in [
+            (0, [2, 3, 1], [0, 1, 2, 3, 4, 5]),
+            (1, [3, 2, 1], [3, 0, 4, 1, 5, 2]),
+            (2, [2, 3, 1], [5, 4, 3, 2, 1, 0]),
+            (3, [3, 2, 1], [2, 5, 1, 4, 0, 3]),
+            (4, [2, 3, 1], [0, 1, 2, 3, 4, 5]),
+        ] {
+            let rotated = rotate_quarter_turns(&img, turns);
+            assert_eq!(rotated.shape(), shape, "turns {}", turns);
+            assert_eq!(rotated.to_vec(), pixels, "turns {}", turns);
+        }
+    }
+
+    #[test]
+    fn test_unrotate_quarter_turns() {
+        // A 2x1 block of bright pixels in a 5x3 image.
+        let (width, height) = (5, 3);
+        let mut img = NdTensor::zeros([height, width, 1]);
+        img[[1, 3, 0]] = 255;
+        img[[1, 4, 0]] = 255;
+        let original = Rect::from_tlbr(1, 3, 2, 5);
+        assert_eq!(bright_rect(&img), original);
+
+        for turns in 0..4 {
+            let rotated = bright_rect(&rotate_quarter_turns(&img, turns));
+            let unrotated = unrotate_quarter_turns(rotated, turns, width as i32, height as i32);
+            assert_eq!(unrotated, original, "turns {}", turns);
+        }
+    }
+
+    #[test]
+    fn test_free_rotation_unmap_rect() {
+        // Without rotation, rectangles are unchanged.
+        let img = NdTensor::zeros([20, 40, 1]);
+        let (rotated, rotation) = FreeRotation::apply(&img, 0.);
+        assert_eq!(rotated.shape(), [20, 40, 1]);
+        let rect = Rect::from_tlbr(2, 3, 10, 30);
+        assert_eq!(rotation.unmap_rect(rect), rect);
+
+        // A block found in a rotated image maps back to roughly where it
+        // was in the original.
+        let mut img = NdTensor::zeros([40, 60, 1]);
+        for y in 10..16 {
+            for x in 20..30 {
+                img[[y, x, 0]] = 255;
+            }
+        }
+        let original = Rect::from_tlbr(10, 20, 16, 30);
+        for degrees in [-15., 10., 90.] {
+            let (rotated, rotation) = FreeRotation::apply(&img, degrees);
+            let unmapped = rotation.unmap_rect(bright_rect(&rotated));
+
+            // The bounding box of the rotated block is larger than the block,
+            // so its mapping contains the original block.
+            assert!(
+                unmapped.left() <= original.left() + 1
+                    && unmapped.top() <= original.top() + 1
+                    && unmapped.right() >= original.right() - 1
+                    && unmapped.bottom() >= original.bottom() - 1,
+                "{} degrees: {:?} does not contain {:?}",
+                degrees,
+                unmapped,
+                original
+            );
+            let center = |r: Rect| (r.left() + r.right(), r.top() + r.bottom());
+            let (cx, cy) = center(unmapped);
+            let (ox, oy) = center(original);
+            assert!(
+                (cx - ox).abs() <= 2 && (cy - oy).abs() <= 2,
+                "{} degrees: {:?} is not centered on {:?}",
+                degrees,
+                unmapped,
+                original
+            );
+        }
+    }
+}
@@ -0,0 +1,469 @@
+use ocrs::{TextItem, TextLine};
+use rten_imageproc::{PointF, Rect, RotatedRect};
+use serde::{Deserialize, Serialize};
+
+use crate::confidence::mean_confidence;
+use crate::decode::ExifOrientation;
+use crate::markup::{format_alto, format_hocr};
+use crate::orientation::Rotation;
+
+/// Format of the response body returned by the `/process` endpoint.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Recognized lines as plain text, separated by newlines.
+    #[default]
+    Text,
+
+    /// Lines, words and their positions as a JSON document.
+    Json,
+
+    /// Lines and words with their bounding boxes as an hOCR (XHTML)
+    /// document.
+    Hocr,
+
+    /// Lines and words with their bounding boxes as an ALTO XML document.
+    Alto,
+}
+
+impl OutputFormat {
+    /// Choose the output format for a request.
+    ///
+    /// An explicit `format` query parameter takes precedence over the
+    /// `Accept` header. Otherwise the first media type in the header that
+    /// names a format is used: `application/json` for JSON,
+    /// `application/xhtml+xml` for hOCR and `application/alto+xml` or
+    /// `text/xml` for ALTO. Quality values are ignored.
+    pub fn negotiate(format: Option<OutputFormat>, accept: Option<&str>) -> OutputFormat {
+        if let Some(format) = format {
+            return format;
+        }
+        accept
+            .into_iter()
+            .flat_map(|accept| accept.split(','))
+            .filter_map(|range| range.split(';').next())
+            .find_map(
+                |media_type| match media_type.trim().to_ascii_lowercase().as_str() {
+                    "application/json" => Some(OutputFormat::Json),
+                    "application/xhtml+xml" => Some(OutputFormat::Hocr),
+                    "application/alto+xml" | "text/xml" => Some(OutputFormat::Alto),
+                    _ => None,
+                },
+            )
+            .unwrap_or(OutputFormat::Text)
+    }
+
+    /// Return whether the format includes confidence scores, which then
+    /// have to be computed.
+    pub fn includes_confidence(self) -> bool {
+        self != OutputFormat::Text
+    }
+}
+
+/// A line of text produced by recognition.
+#[derive(Clone)]
+pub struct RecognizedLine {
+    pub line: TextLine,
+
+    /// Confidence of each character in `line`, if scoring was requested.
+    pub char_confidences: Option<Vec<f32>>,
+
+    /// Name of the region of interest the line was found in.
+    pub region: Option<String>,
+}
+
+impl RecognizedLine {
+    /// Return the average confidence of the characters in this line.
+    pub fn confidence(&self) -> Option<f32> {
+        self.char_confidences.as_ref().map(|scores| {
+            let chars = self.line.chars().iter().map(|c| c.char);
+            mean_confidence(chars.zip(scores.iter().copied()))
+        })
+    }
+}
+
+pub fn format_text_output(text_lines: &[RecognizedLine]) -> String {
+    let lines: Vec<String> = text_lines
+        .iter()
+        .map(|line| line.line.to_string())
+        .collect();
+    lines.join("\n")
+}
+
+/// A point in source image pixel coordinates.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct PointOutput {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl From<PointF> for PointOutput {
+    fn from(p: PointF) -> Self {
+        PointOutput { x: p.x, y: p.y }
+    }
+}
+
+/// An axis-aligned bounding box in source image pixel coordinates.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct BoxOutput {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl From<Rect> for BoxOutput {
+    fn from(r: Rect) -> Self {
+        BoxOutput {
+            x: r.left(),
+            y: r.top(),
+            width: r.width(),
+            height: r.height(),
+        }
+    }
+}
+
+/// Position of a word or line.
+#[derive(Clone, Debug, Serialize)]
+pub struct GeometryOutput {
+    /// Corners of the oriented bounding rectangle, in clockwise order.
+    pub rotated_rect: [PointOutput; 4],
+
+    /// Axis-aligned bounding box.
+    pub bounding_box: BoxOutput,
+}
+
+impl GeometryOutput {
+    fn from_item<TI: TextItem>(item: &TI) -> Self {
+        GeometryOutput {
+            rotated_rect: rotated_rect_corners(&item.rotated_rect()),
+            bounding_box: item.bounding_rect().into(),
+        }
+    }
+}
+
+fn rotated_rect_corners(rect: &RotatedRect) -> [PointOutput; 4] {
+    rect.corners().map(PointOutput::from)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CharOutput {
+    pub text: char,
+    pub confidence: f32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WordOutput {
+    pub text: String,
+    #[serde(flatten)]
+    pub geometry: GeometryOutput,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chars: Option<Vec<CharOutput>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LineOutput {
+    pub text: String,
+    #[serde(flatten)]
+    pub geometry: GeometryOutput,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    pub words: Vec<WordOutput>,
+}
+
+impl LineOutput {
+    fn new(recognized: &RecognizedLine) -> Self {
+        let line = &recognized.line;
+
+        // Split the per-character scores at spaces in the same way that
+        // `TextLine::words` splits the characters.
+        let scored_chars: Option<Vec<(char, f32)>> =
+            recognized.char_confidences.as_ref().map(|scores| {
+                let chars = line.chars().iter().map(|c| c.char);
+                chars.zip(scores.iter().copied()).collect()
+            });
+        let mut word_scores = scored_chars.as_deref().map(|chars| {
+            chars
+                .split(|(c, _)| *c == ' ')
+                .filter(|chars| !chars.is_empty())
+        });
+
+        let words = line
+            .words()
+            .map(|word| {
+                let scores = word_scores.as_mut().and_then(Iterator::next);
+                WordOutput {
+                    text: word.to_string(),
+                    geometry: GeometryOutput::from_item(&word),
+                    confidence: scores.map(|scores| mean_confidence(scores.iter().copied())),
+                    chars: scores.map(|scores| {
+                        scores
+                            .iter()
+                            .map(|&(text, confidence)| CharOutput { text, confidence })
+                            .collect()
+                    }),
+                }
+            })
+            .collect();
+
+        LineOutput {
+            text: line.to_string(),
+            geometry: GeometryOutput::from_item(line),
+            confidence: recognized.confidence(),
+            region: recognized.region.clone(),
+            words,
+        }
+    }
+}
+
+/// Structured OCR result for a single image.
+#[derive(Clone, Debug, Serialize)]
+pub struct OcrOutput {
+    pub width: u32,
+    pub height: u32,
+
+    /// EXIF orientation of the upload, if it was not already upright.
+    /// `width` and `height` are those of the image after applying it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exif_orientation: Option<ExifOrientation>,
+
+    /// Rotation applied to read the text, if automatic rotation was
+    /// requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<Rotation>,
+
+    pub lines: Vec<LineOutput>,
+}
+
+/// Properties of a processed image that are reported alongside its text.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub exif_orientation: Option<ExifOrientation>,
+    pub rotation: Option<Rotation>,
+}
+
+impl OcrOutput {
+    /// Build the structured result for an image from the lines produced by
+    /// recognition.
+    pub fn new(page: &PageInfo, text_lines: &[RecognizedLine]) -> Self {
+        let lines = text_lines.iter().map(LineOutput::new).collect();
+        OcrOutput {
+            width: page.width,
+            height: page.height,
+            exif_orientation: page.exif_orientation,
+            rotation: page.rotation,
+            lines,
+        }
+    }
+}
+
+/// OCR result for a single image, in the requested [OutputFormat].
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum FormattedOutput {
+    Text(String),
+    Json(OcrOutput),
+    Hocr(String),
+    Alto(String),
+}
+
+impl FormattedOutput {
+    pub fn new(format: OutputFormat, page: &PageInfo, lines: &[RecognizedLine]) -> Self {
+        match format {
+            OutputFormat::Text => FormattedOutput::Text(format_text_output(lines)),
+            OutputFormat::Json => FormattedOutput::Json(OcrOutput::new(page, lines)),
+            OutputFormat::Hocr => FormattedOutput::Hocr(format_hocr(&OcrOutput::new(page, lines))),
+            OutputFormat::Alto => FormattedOutput::Alto(format_alto(&OcrOutput::new(page, lines))),
+        }
+    }
+}
+
+/// Lines recognized in an image, before formatting.
+#[derive(Clone)]
+pub struct OcrResult {
+    pub page: PageInfo,
+    pub lines: Vec<RecognizedLine>,
+}
+
+impl OcrResult {
+    pub fn format(&self, format: OutputFormat) -> FormattedOutput {
+        FormattedOutput::new(format, &self.page, &self.lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ocrs::{TextChar, TextLine};
+    use rten_imageproc::Rect;
+
+    use super::{
+        format_text_output, FormattedOutput, OcrOutput, OcrResult, OutputFormat, PageInfo,
+        RecognizedLine,
+    };
+
+    /// Create a line with 10x20 pixel characters, starting at `(left, top)`.
+    fn line(text: &str, left: i32, top: i32, confidences: Option<Vec<f32>>) -> RecognizedLine {
+        let chars = text
+            .chars()
+            .enumerate()
+            .map(|(i, char)| {
+                let x = left + i as i32 * 10;
+                TextChar {
+                    char,
+                    rect: Rect::from_tlbr(top, x, top + 20, x + 10),
+                }
+            })
+            .collect();
+        RecognizedLine {
+            line: TextLine::new(chars),
+            char_confidences: confidences,
+            region: None,
+        }
+    }
+
+    fn page() -> PageInfo {
+        PageInfo {
+            width: 200,
+            height: 100,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let negotiate = OutputFormat::negotiate;
+
+        // An explicit format takes precedence.
+        assert_eq!(
+            negotiate(Some(OutputFormat::Text), Some("application/json")),
+            OutputFormat::Text
+        );
+
+        for (accept, format) in [
+            (None, OutputFormat::Text),
+            (Some("*/*"), OutputFormat::Text),
+            (Some("text/plain"), OutputFormat::Text),
+            (Some("application/json"), OutputFormat::Json),
+            (Some("Application/JSON; charset=utf-8"), OutputFormat::Json),
+            (
+                Some("text/html, application/xhtml+xml;q=0.9, */*;q=0.8"),
+                OutputFormat::Hocr,
+            ),
+            (Some("application/alto+xml"), OutputFormat::Alto),
+            (Some("text/xml"), OutputFormat::Alto),
+            // The first media type that names a format is used.
+            (
+                Some("application/alto+xml, application/json"),
+                OutputFormat::Alto,
+            ),
+        ] {
+            assert_eq!(negotiate(None, accept), format, "{:?}", accept);
+        }
+    }
+
+    #[test]
+    fn test_format_text_output() {
+        let lines = [line("first", 0, 0, None), line("second", 0, 30, None)];
+        assert_eq!(format_text_output(&lines), "first\nsecond");
+        assert_eq!(format_text_output(&[]), "");
+    }
+
+    #[test]
+    fn test_json_output() {
+        let lines = [line(
+            "Hi you",
+            10,
+            20,
+            Some(vec![0.5, 1., 0.1, 0.6, 0.8, 1.]),
+        )];
+        let output = OcrOutput::new(&page(), &lines);
+        assert_eq!((output.width, output.height), (200, 100));
+        assert_eq!(output.lines.len(), 1);
+
+        let line = &output.lines[0];
+        assert_eq!(line.text, "Hi you");
+        let bbox = line.geometry.bounding_box;
+        assert_eq!((bbox.x, bbox.y, bbox.width, bbox.height), (10, 20, 60, 20));
+
+        // Spaces are not counted in the confidence of a line.
+        assert_eq!(line.confidence, Some(0.78));
+
+        let words: Vec<_> = line
+            .words
+            .iter()
+            .map(|word| {
+                let bbox = word.geometry.bounding_box;
+                (
+                    word.text.as_str(),
+                    (bbox.x, bbox.y, bbox.width, bbox.height),
+                    word.confidence,
+                    word.chars.as_ref().map(Vec::len),
+                )
+            })
+            .collect();
+        assert_eq!(
+            words,
+            [
+                ("Hi", (10, 20, 20, 20), Some(0.75), Some(2)),
+                ("you", (40, 20, 30, 20), Some(0.8), Some(3)),
+            ]
+        );
+
+        // The rotated rect of an upright word spans its bounding box.
+        for word in &line.words {
+            let bbox = word.geometry.bounding_box;
+            let corners = word.geometry.rotated_rect;
+            let min_x = corners.iter().map(|p| p.x).fold(f32::INFINITY, f32::min);
+            let max_y = corners
+                .iter()
+                .map(|p| p.y)
+                .fold(f32::NEG_INFINITY, f32::max);
+            assert!((min_x - bbox.x as f32).abs() <= 1., "{:?}", corners);
+            assert!(
+                (max_y - (bbox.y + bbox.height) as f32).abs() <= 1.,
+                "{:?}",
+                corners
+            );
+        }
+    }
+
+    #[test]
+    fn test_json_output_without_confidence() {
+        let output = OcrOutput::new(&page(), &[line("Hi you", 0, 0, None)]);
+        let line = &output.lines[0];
+        assert_eq!(line.confidence, None);
+        assert!(line
+            .words
+            .iter()
+            .all(|word| word.confidence.is_none() && word.chars.is_none()));
+    }
+
+    #[test]
+    fn test_ocr_result_format() {
+        let result = OcrResult {
+            page: page(),
+            lines: vec![line("text", 0, 0, None)],
+        };
+        assert!(
+            matches!(result.format(OutputFormat::Text), FormattedOutput::Text(text) if text == "text")
+        );
+        assert!(matches!(
+            result.format(OutputFormat::Json),
+            FormattedOutput::Json(output) if output.lines.len() == 1
+        ));
+        assert!(matches!(
+            result.format(OutputFormat::Hocr),
+            FormattedOutput::Hocr(doc) if doc.contains("ocrx_word")
+        ));
+        assert!(matches!(
+            result.format(OutputFormat::Alto),
+            FormattedOutput::Alto(doc) if doc.contains("<String")
+        ));
+    }
+}
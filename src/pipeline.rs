@@ -0,0 +1,381 @@
+use std::fmt;
+
+use ocrs::{DimOrder, ImageSource, ImageSourceError, TextChar, TextItem, TextLine};
+use rten_imageproc::{Rect, RotatedRect};
+use rten_tensor::prelude::*;
+use rten_tensor::NdTensor;
+
+use crate::confidence::ConfidenceScorer;
+use crate::engine::{DecodeMode, OcrEngines};
+use crate::orientation::{
+    estimate_skew, rotate_quarter_turns, unrotate_quarter_turns, FreeRotation, Rotation,
+};
+use crate::output::RecognizedLine;
+use crate::preprocess::{preprocess, PreprocessError, PreprocessStep};
+use crate::roi::Roi;
+use crate::timing::time_stage;
+
+/// Per-request options that affect how an image is processed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OcrOptions {
+    /// Decode method to use instead of the configured default.
+    pub decode_method: Option<DecodeMode>,
+
+    /// Compute per-character confidence scores.
+    ///
+    /// Scoring re-runs the recognition model, so it is only done when the
+    /// scores are returned or needed for filtering.
+    pub score_confidence: bool,
+
+    /// Drop lines whose average character confidence is below this value,
+    /// from 0 to 1. Implies `score_confidence`.
+    pub min_confidence: Option<f32>,
+
+    /// Preprocessing applied to the image before detection.
+    pub preprocess: Vec<PreprocessStep>,
+
+    /// Try each 90 degree rotation of the image and straighten skewed text,
+    /// keeping the result with the most confidently recognized text.
+    pub auto_rotate: bool,
+}
+
+/// Text recognized in an image.
+#[derive(Clone)]
+pub struct OcrPage {
+    pub lines: Vec<RecognizedLine>,
+
+    /// Rotation that was applied to read the text, if `auto_rotate` was set.
+    pub rotation: Option<Rotation>,
+}
+
+/// Stage of the OCR pipeline that failed.
+#[derive(Debug)]
+pub enum PipelineError {
+    /// The image has zero width or height.
+    EmptyImage,
+    /// The image has an unsupported layout.
+    InvalidImage(ImageSourceError),
+    /// The requested preprocessing could not be applied.
+    Preprocess(PreprocessError),
+    PrepareInput(anyhow::Error),
+    DetectWords(anyhow::Error),
+    RecognizeText(anyhow::Error),
+    ScoreText(anyhow::Error),
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::EmptyImage => write!(f, "Image has zero width or height"),
+            PipelineError::InvalidImage(err) => write!(f, "Invalid image: {}", err),
+            PipelineError::Preprocess(err) => write!(f, "Failed to preprocess image: {}", err),
+            PipelineError::PrepareInput(err) => write!(f, "Failed to prepare input: {}", err),
+            PipelineError::DetectWords(err) => write!(f, "Failed to detect words: {}", err),
+            PipelineError::RecognizeText(err) => write!(f, "Failed to recognize text: {}", err),
+            PipelineError::ScoreText(err) => write!(f, "Failed to score text: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+/// Detect and recognize text in an HWC RGB image.
+pub fn run_ocr(
+    engines: &OcrEngines,
+    scorer: &ConfidenceScorer,
+    color_img: &NdTensor<u8, 3>,
+    opts: &OcrOptions,
+) -> Result<OcrPage, PipelineError> {
+    if opts.auto_rotate {
+        return run_ocr_auto_rotate(engines, scorer, color_img, opts);
+    }
+    let (lines, _) = recognize(engines, scorer, color_img, opts)?;
+    Ok(OcrPage {
+        lines,
+        rotation: None,
+    })
+}
+
+/// Run OCR on each 90 degree rotation of an image, then on the best one
+/// with its skew corrected, and return the result with the highest
+/// [text_score].
+fn run_ocr_auto_rotate(
+    engines: &OcrEngines,
+    scorer: &ConfidenceScorer,
+    color_img: &NdTensor<u8, 3>,
+    opts: &OcrOptions,
+) -> Result<OcrPage, PipelineError> {
+    let [height, width, _] = color_img.shape();
+
+    // Orientations are compared by confidence, so it must be scored.
+    let opts = OcrOptions {
+        score_confidence: true,
+        auto_rotate: false,
+        ..opts.clone()
+    };
+
+    let mut best = None;
+    for quarter_turns in 0..4 {
+        let rotated = rotate_quarter_turns(color_img, quarter_turns);
+        let (lines, word_rects) = recognize(engines, scorer, &rotated, &opts)?;
+        let score = text_score(&lines);
+        // Earlier orientations win ties, so upright images are left alone.
+        if best
+            .as_ref()
+            .is_none_or(|(_, _, _, _, best_score)| score > *best_score)
+        {
+            best = Some((quarter_turns, rotated, lines, word_rects, score));
+        }
+    }
+    let (quarter_turns, rotated, mut lines, word_rects, score) =
+        best.expect("at least one orientation is tried");
+
+    let mut deskew = 0.;
+    if let Some(skew) = estimate_skew(&word_rects) {
+        let (deskewed, rotation) = FreeRotation::apply(&rotated, -skew);
+        let (deskewed_lines, _) = recognize(engines, scorer, &deskewed, &opts)?;
+        if text_score(&deskewed_lines) > score {
+            lines = deskewed_lines
+                .into_iter()
+                .map(|line| RecognizedLine {
+                    line: map_char_rects(&line.line, |rect| rotation.unmap_rect(rect)),
+                    ..line
+                })
+                .collect();
+            deskew = -skew;
+        }
+    }
+
+    let lines = lines
+        .into_iter()
+        .map(|line| RecognizedLine {
+            line: map_char_rects(&line.line, |rect| {
+                unrotate_quarter_turns(rect, quarter_turns, width as i32, height as i32)
+            }),
+            ..line
+        })
+        .collect();
+    Ok(OcrPage {
+        lines,
+        rotation: Some(Rotation {
+            orientation: quarter_turns * 90,
+            deskew,
+        }),
+    })
+}
+
+/// Return a measure of how much text was confidently recognized: the sum of
+/// the confidence of every character.
+fn text_score(lines: &[RecognizedLine]) -> f32 {
+    lines
+        .iter()
+        .filter_map(|line| {
+            let scores = line.char_confidences.as_ref()?;
+            let chars = line.line.chars().iter();
+            Some(
+                chars
+                    .zip(scores)
+                    .filter(|(c, _)| c.char != ' ')
+                    .map(|(_, score)| score)
+                    .sum::<f32>(),
+            )
+        })
+        .sum()
+}
+
+/// Detect and recognize text in an HWC RGB image, and return the lines and
+/// the oriented rectangles of the detected words.
+fn recognize(
+    engines: &OcrEngines,
+    scorer: &ConfidenceScorer,
+    color_img: &NdTensor<u8, 3>,
+    opts: &OcrOptions,
+) -> Result<(Vec<RecognizedLine>, Vec<RotatedRect>), PipelineError> {
+    let engine = engines.detector();
+
+    let [height, width, _] = color_img.shape();
+    if height == 0 || width == 0 {
+        return Err(PipelineError::EmptyImage);
+    }
+
+    // Apply the requested preprocessing. Positions of the recognized text are
+    // mapped back to the original image below.
+    let preprocessed;
+    let (color_img, scale_y, scale_x) = if opts.preprocess.is_empty() {
+        (color_img, 1., 1.)
+    } else {
+        let (img, scale_y, scale_x) =
+            time_stage("preprocess", || preprocess(color_img, &opts.preprocess))
+                .map_err(PipelineError::Preprocess)?;
+        preprocessed = img;
+        (&preprocessed, scale_y, scale_x)
+    };
+
+    // Preprocess image for use with OCR engine.
+    let color_img_source = ImageSource::from_tensor(color_img.view(), DimOrder::Hwc)
+        .map_err(PipelineError::InvalidImage)?;
+    let ocr_input = time_stage("prepare_input", || engine.prepare_input(color_img_source))
+        .map_err(PipelineError::PrepareInput)?;
+    let word_rects = time_stage("detect_words", || engine.detect_words(&ocr_input))
+        .map_err(PipelineError::DetectWords)?;
+    let line_rects = time_stage("find_text_lines", || {
+        engine.find_text_lines(&ocr_input, &word_rects)
+    });
+    let recognizer = engines
+        .recognizer(opts.decode_method)
+        .map_err(PipelineError::RecognizeText)?;
+    let line_texts = time_stage("recognize_text", || {
+        recognizer.recognize_text(&ocr_input, &line_rects)
+    })
+    .map_err(PipelineError::RecognizeText)?;
+
+    let score_lines = opts.score_confidence || opts.min_confidence.is_some();
+    let mut lines = Vec::new();
+    for (word_rects, line) in line_rects.iter().zip(line_texts) {
+        let Some(line) = line else {
+            continue;
+        };
+        let char_confidences = if score_lines {
+            let scores = time_stage("score_text", || {
+                scorer.score_line(engine, &ocr_input, word_rects, &line)
+            })
+            .map_err(PipelineError::ScoreText)?;
+            Some(scores)
+        } else {
+            None
+        };
+        let line = if scale_y != 1. || scale_x != 1. {
+            map_char_rects(&line, |rect| {
+                let scale = |v: i32, factor: f32| (v as f32 / factor).round() as i32;
+                Rect::from_tlbr(
+                    scale(rect.top(), scale_y),
+                    scale(rect.left(), scale_x),
+                    scale(rect.bottom(), scale_y),
+                    scale(rect.right(), scale_x),
+                )
+            })
+        } else {
+            line
+        };
+        lines.push(RecognizedLine {
+            line,
+            char_confidences,
+            region: None,
+        });
+    }
+    if let Some(min_confidence) = opts.min_confidence {
+        retain_confident_lines(&mut lines, min_confidence);
+    }
+
+    Ok((lines, word_rects))
+}
+
+/// Drop lines whose average character confidence is below `min_confidence`.
+/// Lines without scores are dropped.
+fn retain_confident_lines(lines: &mut Vec<RecognizedLine>, min_confidence: f32) {
+    lines.retain(|line| line.confidence().unwrap_or(0.) >= min_confidence);
+}
+
+/// Run OCR separately on each region of an HWC RGB image.
+///
+/// Each region is cropped and passed to `run`, which is usually [run_ocr].
+/// Lines are tagged with the name of the region they were found in and their
+/// coordinates are relative to the whole image. Regions that do not overlap
+/// the image are skipped. The rotation reported is that of the first region
+/// that has one.
+pub fn run_ocr_regions<F>(
+    color_img: &NdTensor<u8, 3>,
+    regions: &[Roi],
+    mut run: F,
+) -> Result<OcrPage, PipelineError>
+where
+    F: FnMut(&NdTensor<u8, 3>) -> Result<OcrPage, PipelineError>,
+{
+    let [height, width, _] = color_img.shape();
+    let mut lines = Vec::new();
+    let mut rotation = None;
+    for region in regions {
+        let Some(rect) = region.to_rect(width, height) else {
+            continue;
+        };
+        let crop = color_img
+            .slice::<3, _>((
+                rect.top() as usize..rect.bottom() as usize,
+                rect.left() as usize..rect.right() as usize,
+                ..,
+            ))
+            .to_tensor();
+        let page = run(&crop)?;
+        rotation = rotation.or(page.rotation);
+        for mut line in page.lines {
+            let (dy, dx) = (rect.top(), rect.left());
+            line.line = map_char_rects(&line.line, |r| {
+                Rect::new(
+                    r.top_left().translate(dy, dx),
+                    r.bottom_right().translate(dy, dx),
+                )
+            });
+            line.region = Some(region.name.clone());
+            lines.push(line);
+        }
+    }
+    Ok(OcrPage { lines, rotation })
+}
+
+/// Transform the character positions of a line, eg. to map them from a
+/// cropped or scaled image back to the original.
+fn map_char_rects(line: &TextLine, f: impl Fn(Rect) -> Rect) -> TextLine {
+    let chars = line
+        .chars()
+        .iter()
+        .map(|c| TextChar {
+            char: c.char,
+            rect: f(c.rect),
+        })
+        .collect();
+    TextLine::new(chars)
+}
+
+#[cfg(test)]
+mod tests {
+    use ocrs::{TextChar, TextLine};
+    use rten_imageproc::Rect;
+
+    use super::retain_confident_lines;
+    use crate::output::RecognizedLine;
+
+    fn line(text: &str, confidences: Option<Vec<f32>>) -> RecognizedLine {
+        let chars = text
+            .chars()
+            .enumerate()
+            .map(|(i, char)| TextChar {
+                char,
+                rect: Rect::from_tlbr(0, i as i32 * 10, 20, i as i32 * 10 + 10),
+            })
+            .collect();
+        RecognizedLine {
+            line: TextLine::new(chars),
+            char_confidences: confidences,
+            region: None,
+        }
+    }
+
+    #[test]
+    fn test_retain_confident_lines() {
+        let mut lines = vec![
+            line("ab", Some(vec![1., 0.5])),
+            line("cd", Some(vec![1., 0.25])),
+            // The space does not lower the average.
+            line("e f", Some(vec![0.75, 0., 0.75])),
+            line("gh", None),
+        ];
+        retain_confident_lines(&mut lines, 0.75);
+        let texts: Vec<String> = lines.iter().map(|l| l.line.to_string()).collect();
+        assert_eq!(texts, ["ab", "e f"]);
+
+        retain_confident_lines(&mut lines, 0.);
+        assert_eq!(lines.len(), 2);
+        retain_confident_lines(&mut lines, 1.);
+        assert!(lines.is_empty());
+    }
+}
@@ -0,0 +1,469 @@
+use std::fmt;
+
+use image::imageops::{self, FilterType};
+use image::RgbImage;
+use rten_tensor::prelude::*;
+use rten_tensor::NdTensor;
+use serde::{Deserialize, Deserializer};
+
+/// Largest factor an image can be scaled by.
+const MAX_SCALE: f32 = 4.;
+
+/// Largest radius of the denoising filter.
+const MAX_DENOISE_RADIUS: u32 = 3;
+
+/// Return the largest number of pixels that scaling may produce.
+///
+/// This matches the allocation limit of the image decoder, so scaling cannot
+/// create an image larger than one that could have been uploaded.
+fn max_scaled_pixels() -> u64 {
+    let bytes_per_pixel = 3;
+    image::Limits::default().max_alloc.unwrap_or(u64::MAX) / bytes_per_pixel
+}
+
+/// A step of the preprocessing applied to an image before OCR.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PreprocessStep {
+    /// Resize the image by a factor. Small text is often recognized better
+    /// after upscaling.
+    Scale(f32),
+
+    /// Convert to grayscale.
+    Grayscale,
+
+    /// Stretch brightness so that the darkest and brightest 1% of pixels
+    /// become black and white.
+    Contrast,
+
+    /// Convert to black and white, comparing each pixel with the mean
+    /// brightness of the surrounding square of the given (odd) size.
+    Threshold(u32),
+
+    /// Invert colors, eg. to turn light-on-dark text into dark-on-light.
+    Invert,
+
+    /// Unsharp mask with the given blur radius (sigma).
+    Sharpen(f32),
+
+    /// Median filter with the given radius.
+    Denoise(u32),
+}
+
+impl PreprocessStep {
+    fn parse(spec: &str) -> Result<PreprocessStep, ParsePreprocessError> {
+        let invalid = || ParsePreprocessError(spec.to_string());
+        let (name, arg) = match spec.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (spec, None),
+        };
+        let step = match (name, arg) {
+            ("scale", Some(arg)) => {
+                let factor: f32 = arg.parse().map_err(|_| invalid())?;
+                if !(factor > 0. && factor <= MAX_SCALE) {
+                    return Err(invalid());
+                }
+                PreprocessStep::Scale(factor)
+            }
+            ("grayscale", None) => PreprocessStep::Grayscale,
+            ("contrast", None) => PreprocessStep::Contrast,
+            ("threshold", arg) => {
+                let size: u32 = arg.map_or(Ok(31), str::parse).map_err(|_| invalid())?;
+                if size < 3 || size.is_multiple_of(2) {
+                    return Err(invalid());
+                }
+                PreprocessStep::Threshold(size)
+            }
+            ("invert", None) => PreprocessStep::Invert,
+            ("sharpen", arg) => {
+                let sigma: f32 = arg.map_or(Ok(1.), str::parse).map_err(|_| invalid())?;
+                if !(sigma > 0. && sigma.is_finite()) {
+                    return Err(invalid());
+                }
+                PreprocessStep::Sharpen(sigma)
+            }
+            ("denoise", arg) => {
+                let radius: u32 = arg.map_or(Ok(1), str::parse).map_err(|_| invalid())?;
+                if !(1..=MAX_DENOISE_RADIUS).contains(&radius) {
+                    return Err(invalid());
+                }
+                PreprocessStep::Denoise(radius)
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(step)
+    }
+}
+
+/// Error returned when parsing a list of preprocessing steps fails.
+#[derive(Debug)]
+pub struct ParsePreprocessError(String);
+
+impl fmt::Display for ParsePreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid preprocessing step \"{}\"", self.0)
+    }
+}
+
+impl std::error::Error for ParsePreprocessError {}
+
+/// Reasons preprocessing an image failed.
+#[derive(Debug)]
+pub enum PreprocessError {
+    /// Scaling would produce an image with more pixels than allowed.
+    TooLarge { width: u64, height: u64 },
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreprocessError::TooLarge { width, height } => write!(
+                f,
+                "Scaling would produce a {}x{} image, which exceeds the limit of {} pixels",
+                width,
+                height,
+                max_scaled_pixels()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PreprocessError {}
+
+/// Parse a comma-separated list of preprocessing steps, eg.
+/// `scale:2,grayscale,threshold:31,invert`.
+pub fn parse_steps(s: &str) -> Result<Vec<PreprocessStep>, ParsePreprocessError> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(PreprocessStep::parse)
+        .collect()
+}
+
+/// Deserialize an optional `preprocess` query parameter with [parse_steps].
+pub fn deserialize_steps<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<PreprocessStep>>, D::Error> {
+    let value: Option<String> = Option::deserialize(deserializer)?;
+    value
+        .map(|s| parse_steps(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Apply preprocessing steps, in order, to an HWC RGB image.
+///
+/// Returns the processed image and the factors by which it was scaled
+/// vertically and horizontally. Fails if a scaling step would produce an
+/// image that is too large to allocate.
+pub fn preprocess(
+    color_img: &NdTensor<u8, 3>,
+    steps: &[PreprocessStep],
+) -> Result<(NdTensor<u8, 3>, f32, f32), PreprocessError> {
+    let [height, width, _] = color_img.shape();
+    let data: Vec<u8> = color_img.iter().copied().collect();
+    let mut img = RgbImage::from_raw(width as u32, height as u32, data)
+        .expect("tensor size should match image size");
+
+    for step in steps {
+        img = match *step {
+            PreprocessStep::Scale(factor) => {
+                let scaled = |size: u32| ((size as f64 * factor as f64).round() as u64).max(1);
+                let (width, height) = img.dimensions();
+                let (width, height) = (scaled(width), scaled(height));
+                if width * height > max_scaled_pixels() {
+                    return Err(PreprocessError::TooLarge { width, height });
+                }
+                imageops::resize(&img, width as u32, height as u32, FilterType::CatmullRom)
+            }
+            PreprocessStep::Grayscale => map_pixels(img, |[r, g, b]| {
+                let luma = luma(r, g, b);
+                [luma, luma, luma]
+            }),
+            PreprocessStep::Contrast => stretch_contrast(img),
+            PreprocessStep::Threshold(size) => adaptive_threshold(img, size),
+            PreprocessStep::Invert => map_pixels(img, |p| p.map(|v| 255 - v)),
+            PreprocessStep::Sharpen(sigma) => imageops::unsharpen(&img, sigma, 0),
+            PreprocessStep::Denoise(radius) => median_filter(&img, radius),
+        };
+    }
+
+    let (new_width, new_height) = img.dimensions();
+    let scale_y = new_height as f32 / height as f32;
+    let scale_x = new_width as f32 / width as f32;
+    let tensor = NdTensor::from_data([new_height as usize, new_width as usize, 3], img.into_raw());
+    Ok((tensor, scale_y, scale_x))
+}
+
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000) as u8
+}
+
+fn map_pixels(mut img: RgbImage, f: impl Fn([u8; 3]) -> [u8; 3]) -> RgbImage {
+    for pixel in img.pixels_mut() {
+        pixel.0 = f(pixel.0);
+    }
+    img
+}
+
+fn stretch_contrast(img: RgbImage) -> RgbImage {
+    let mut histogram = [0usize; 256];
+    for pixel in img.pixels() {
+        let [r, g, b] = pixel.0;
+        histogram[luma(r, g, b) as usize] += 1;
+    }
+
+    // Ignore the darkest and brightest 1% so that a few outliers do not
+    // prevent stretching.
+    let clip = img.pixels().len() / 100;
+    let percentile = |mut levels: Box<dyn Iterator<Item = usize>>| {
+        let mut count = 0;
+        levels
+            .find(|&level| {
+                count += histogram[level];
+                count > clip
+            })
+            .unwrap_or(0)
+    };
+    let low = percentile(Box::new(0..256)) as f32;
+    let high = percentile(Box::new((0..256).rev())) as f32;
+    if high <= low {
+        return img;
+    }
+
+    let scale = 255. / (high - low);
+    map_pixels(img, |p| {
+        p.map(|v| ((v as f32 - low) * scale).clamp(0., 255.).round() as u8)
+    })
+}
+
+/// Binarize an image by comparing each pixel's brightness with the mean
+/// brightness of the `size` x `size` window around it.
+///
+/// Unlike a global threshold, this handles uneven lighting and text on
+/// backgrounds that vary across the image.
+fn adaptive_threshold(img: RgbImage, size: u32) -> RgbImage {
+    // Pixels must be this much darker than their surroundings to be
+    // considered text.
+    const OFFSET: u32 = 10;
+
+    let (width, height) = img.dimensions();
+    let (w, h) = (width as usize, height as usize);
+
+    // Summed-area table of brightness, with an extra row and column of zeros.
+    let mut integral = vec![0u64; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row_sum = 0;
+        for x in 0..w {
+            let [r, g, b] = img.get_pixel(x as u32, y as u32).0;
+            row_sum += luma(r, g, b) as u64;
+            integral[(y + 1) * (w + 1) + x + 1] = integral[y * (w + 1) + x + 1] + row_sum;
+        }
+    }
+
+    let radius = (size / 2) as usize;
+    let mut out = img.clone();
+    for (x, y, pixel) in out.enumerate_pixels_mut() {
+        let (x, y) = (x as usize, y as usize);
+        let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(w));
+        let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(h));
+        let sum = integral[y1 * (w + 1) + x1] + integral[y0 * (w + 1) + x0]
+            - integral[y0 * (w + 1) + x1]
+            - integral[y1 * (w + 1) + x0];
+        let mean = sum / ((x1 - x0) * (y1 - y0)) as u64;
+        let [r, g, b] = pixel.0;
+        let value = if (luma(r, g, b) as u64 + OFFSET as u64) < mean {
+            0
+        } else {
+            255
+        };
+        pixel.0 = [value; 3];
+    }
+    out
+}
+
+/// Replace each channel of each pixel with the median of the surrounding
+/// `2 * radius + 1` square. This removes speckle noise while keeping edges.
+fn median_filter(img: &RgbImage, radius: u32) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let radius = radius as i64;
+    let mut window = Vec::with_capacity(((2 * radius + 1) * (2 * radius + 1)) as usize);
+    RgbImage::from_fn(width, height, |x, y| {
+        let mut pixel = [0u8; 3];
+        for (chan, value) in pixel.iter_mut().enumerate() {
+            window.clear();
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let sx = (x as i64 + dx).clamp(0, width as i64 - 1) as u32;
+                    let sy = (y as i64 + dy).clamp(0, height as i64 - 1) as u32;
+                    window.push(img.get_pixel(sx, sy).0[chan]);
+                }
+            }
+            let mid = window.len() / 2;
+            *value = *window.select_nth_unstable(mid).1;
+        }
+        image::Rgb(pixel)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_tensor::prelude::*;
+    use rten_tensor::NdTensor;
+
+    use super::{parse_steps, preprocess, PreprocessError, PreprocessStep};
+
+    /// Create an RGB image where every pixel is `f(x, y)`.
+    fn image(width: usize, height: usize, f: impl Fn(usize, usize) -> [u8; 3]) -> NdTensor<u8, 3> {
+        NdTensor::from_fn([height, width, 3], |[y, x, c]| f(x, y)[c])
+    }
+
+    fn pixel(img: &NdTensor<u8, 3>, x: usize, y: usize) -> [u8; 3] {
+        [0, 1, 2].map(|c| img[[y, x, c]])
+    }
+
+    fn apply(img: &NdTensor<u8, 3>, step: PreprocessStep) -> NdTensor<u8, 3> {
+        preprocess(img, &[step]).unwrap().0
+    }
+
+    #[test]
+    fn test_parse_steps() {
+        assert_eq!(
+            parse_steps("scale:2, grayscale,contrast,threshold,invert,sharpen,denoise,").unwrap(),
+            [
+                PreprocessStep::Scale(2.),
+                PreprocessStep::Grayscale,
+                PreprocessStep::Contrast,
+                PreprocessStep::Threshold(31),
+                PreprocessStep::Invert,
+                PreprocessStep::Sharpen(1.),
+                PreprocessStep::Denoise(1),
+            ]
+        );
+        assert_eq!(
+            parse_steps("threshold:15,sharpen:2.5,denoise:3,scale:0.5").unwrap(),
+            [
+                PreprocessStep::Threshold(15),
+                PreprocessStep::Sharpen(2.5),
+                PreprocessStep::Denoise(3),
+                PreprocessStep::Scale(0.5),
+            ]
+        );
+        assert_eq!(parse_steps("").unwrap(), []);
+
+        for spec in [
+            "scale",
+            "scale:0",
+            "scale:5",
+            "scale:NaN",
+            "threshold:4",
+            "threshold:1",
+            "sharpen:-1",
+            "sharpen:inf",
+            "denoise:0",
+            "denoise:4",
+            "grayscale:1",
+            "blur",
+        ] {
+            let err = parse_steps(spec).unwrap_err();
+            assert!(err.to_string().contains(spec), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_scale() {
+        let img = image(4, 3, |_, _| [10, 20, 30]);
+
+        let (scaled, scale_y, scale_x) = preprocess(&img, &[PreprocessStep::Scale(2.)]).unwrap();
+        assert_eq!(scaled.shape(), [6, 8, 3]);
+        assert_eq!((scale_y, scale_x), (2., 2.));
+        assert_eq!(pixel(&scaled, 5, 4), [10, 20, 30]);
+
+        // Sizes are rounded, so the factors may differ per axis.
+        let (scaled, scale_y, scale_x) = preprocess(&img, &[PreprocessStep::Scale(0.5)]).unwrap();
+        assert_eq!(scaled.shape(), [2, 2, 3]);
+        assert_eq!((scale_y, scale_x), (2. / 3., 0.5));
+    }
+
+    #[test]
+    fn test_scale_too_large() {
+        let img = NdTensor::zeros([3000, 4000, 3]);
+        let err = preprocess(&img, &[PreprocessStep::Scale(4.)]).unwrap_err();
+        assert!(matches!(
+            err,
+            PreprocessError::TooLarge {
+                width: 16000,
+                height: 12000
+            }
+        ));
+    }
+
+    #[test]
+    fn test_grayscale_and_invert() {
+        let img = image(2, 1, |x, _| if x == 0 { [255, 0, 0] } else { [0, 0, 255] });
+
+        let gray = apply(&img, PreprocessStep::Grayscale);
+        assert_eq!(pixel(&gray, 0, 0), [76, 76, 76]);
+        assert_eq!(pixel(&gray, 1, 0), [29, 29, 29]);
+
+        let inverted = apply(&img, PreprocessStep::Invert);
+        assert_eq!(pixel(&inverted, 0, 0), [0, 255, 255]);
+        assert_eq!(pixel(&inverted, 1, 0), [255, 255, 0]);
+    }
+
+    #[test]
+    fn test_contrast() {
+        // A low-contrast gradient from 100 to 150.
+        let img = image(51, 4, |x, _| [100 + x as u8; 3]);
+        let stretched = apply(&img, PreprocessStep::Contrast);
+        assert_eq!(pixel(&stretched, 0, 0), [0; 3]);
+        assert_eq!(pixel(&stretched, 50, 0), [255; 3]);
+        assert!(pixel(&stretched, 25, 0)[0].abs_diff(128) <= 3);
+
+        // A flat image is left as it is.
+        let flat = image(8, 8, |_, _| [90; 3]);
+        assert_eq!(apply(&flat, PreprocessStep::Contrast), flat);
+    }
+
+    #[test]
+    fn test_threshold() {
+        // Dark text on a background that gets brighter from left to right.
+        let img = image(60, 20, |x, y| {
+            let background = 120 + x as u8 * 2;
+            let text = (8..12).contains(&y) && x % 10 < 3;
+            [if text { background - 60 } else { background }; 3]
+        });
+        let binary = apply(&img, PreprocessStep::Threshold(15));
+        for (x, y) in [(1, 9), (51, 10)] {
+            assert_eq!(pixel(&binary, x, y), [0; 3], "{} {}", x, y);
+        }
+        for (x, y) in [(5, 9), (55, 10), (1, 2), (51, 17)] {
+            assert_eq!(pixel(&binary, x, y), [255; 3], "{} {}", x, y);
+        }
+    }
+
+    #[test]
+    fn test_denoise() {
+        let img = image(
+            7,
+            7,
+            |x, y| if (x, y) == (3, 3) { [255; 3] } else { [20; 3] },
+        );
+        let denoised = apply(&img, PreprocessStep::Denoise(1));
+        assert_eq!(pixel(&denoised, 3, 3), [20; 3]);
+    }
+
+    #[test]
+    fn test_steps_apply_in_order() {
+        let img = image(2, 2, |_, _| [200, 100, 0]);
+        let gray_then_invert =
+            preprocess(&img, &[PreprocessStep::Grayscale, PreprocessStep::Invert])
+                .unwrap()
+                .0;
+        let invert_then_gray =
+            preprocess(&img, &[PreprocessStep::Invert, PreprocessStep::Grayscale])
+                .unwrap()
+                .0;
+        // Inverting and then converting to grayscale rounds differently.
+        assert_eq!(pixel(&gray_then_invert, 0, 0), [137; 3]);
+        assert_eq!(pixel(&invert_then_gray, 0, 0), [136; 3]);
+    }
+}
@@ -0,0 +1,291 @@
+use std::fmt;
+
+use rten_imageproc::Rect;
+use serde::{Deserialize, Deserializer};
+
+/// Unit of the bounds of a [Roi].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoiUnit {
+    /// Pixels of the image.
+    Pixels,
+
+    /// Percentages of the image width (for `x` and `width`) or height (for
+    /// `y` and `height`).
+    Percent,
+
+    /// Fractions of the image width or height, like [RoiUnit::Percent].
+    Fraction,
+}
+
+/// A region of interest that OCR is restricted to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Roi {
+    /// Name used to tag results from this region.
+    pub name: String,
+
+    /// Left, top, width and height of the region, in `unit`.
+    pub bounds: [f32; 4],
+
+    pub unit: RoiUnit,
+}
+
+impl Roi {
+    /// Return the region in pixels for an image of the given size, clipped to
+    /// the image, or `None` if it does not overlap the image.
+    pub fn to_rect(&self, width: usize, height: usize) -> Option<Rect> {
+        let [x, y, w, h] = match self.unit {
+            RoiUnit::Pixels => self.bounds,
+            RoiUnit::Percent | RoiUnit::Fraction => {
+                let scale = if self.unit == RoiUnit::Percent {
+                    100.
+                } else {
+                    1.
+                };
+                let [x, y, w, h] = self.bounds.map(|v| v / scale);
+                [
+                    x * width as f32,
+                    y * height as f32,
+                    w * width as f32,
+                    h * height as f32,
+                ]
+            }
+        };
+        let image_rect = Rect::from_hw(height as i32, width as i32);
+        let rect = Rect::from_tlbr(
+            y.round() as i32,
+            x.round() as i32,
+            (y + h).round() as i32,
+            (x + w).round() as i32,
+        )
+        .intersection(image_rect);
+        if rect.is_empty() {
+            None
+        } else {
+            Some(rect)
+        }
+    }
+}
+
+/// Error returned when parsing a list of regions fails.
+#[derive(Debug)]
+pub struct ParseRoiError {
+    spec: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for ParseRoiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid region \"{}\": {}", self.spec, self.reason)
+    }
+}
+
+impl std::error::Error for ParseRoiError {}
+
+/// Parse one bound of a region: a percentage with a `%` suffix, a whole
+/// number of pixels with a `px` suffix, or a number without a unit, which is
+/// in `unitless`.
+fn parse_bound(value: &str, unitless: RoiUnit) -> Result<(f32, RoiUnit), &'static str> {
+    let value = value.trim();
+    if let Some(percent) = value.strip_suffix('%') {
+        let percent: f32 = percent
+            .trim()
+            .parse()
+            .map_err(|_| "expected a number before \"%\"")?;
+        if !(percent.is_finite() && percent >= 0.) {
+            return Err("percentages must not be negative");
+        }
+        return Ok((percent, RoiUnit::Percent));
+    }
+    let (value, unit) = match value.strip_suffix("px") {
+        Some(pixels) => (pixels.trim(), RoiUnit::Pixels),
+        None => (value, unitless),
+    };
+    match unit {
+        RoiUnit::Fraction => {
+            let fraction: f32 = value.parse().map_err(|_| "expected a number")?;
+            if !(0. ..=1.).contains(&fraction) {
+                return Err("fractions must be between 0 and 1");
+            }
+            Ok((fraction, RoiUnit::Fraction))
+        }
+        _ => {
+            let pixels: u32 = value.parse().map_err(|_| {
+                "values must be whole numbers of pixels, fractions with a decimal point, \
+                 or percentages with a \"%\" suffix"
+            })?;
+            Ok((pixels as f32, RoiUnit::Pixels))
+        }
+    }
+}
+
+/// Return whether a value without a unit is written as a fraction, ie. with a
+/// decimal point.
+fn is_fraction(value: &str) -> bool {
+    let value = value.trim();
+    !value.ends_with('%') && !value.ends_with("px") && value.contains('.')
+}
+
+/// Parse a list of regions of the form `[name:]x,y,width,height`, separated
+/// by `;`.
+///
+/// The values of a region are either all percentages of the image size,
+/// with a `%` suffix, all fractions of the image size, or all whole numbers
+/// of pixels, with an optional `px` suffix. eg.
+/// `subtitles:0%,80%,100%,20%;ticker:10,20,300px,40px;logo:0.9,0,0.1,0.1`.
+/// Values without a unit are fractions if any of them has a decimal point,
+/// so `0,0,1,1` is one pixel and `0,0,1.0,1.0` the whole image. Unnamed
+/// regions are named after their position in the list (`roi0`, `roi1`, ...).
+pub fn parse_rois(s: &str) -> Result<Vec<Roi>, ParseRoiError> {
+    s.split(';')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .enumerate()
+        .map(|(index, spec)| {
+            let invalid = |reason| ParseRoiError {
+                spec: spec.to_string(),
+                reason,
+            };
+            let (name, coords) = match spec.split_once(':') {
+                Some((name, coords)) => (name.trim().to_string(), coords),
+                None => (format!("roi{}", index), spec),
+            };
+            if name.is_empty() {
+                return Err(invalid("the name must not be empty"));
+            }
+            let unitless = if coords.split(',').any(is_fraction) {
+                RoiUnit::Fraction
+            } else {
+                RoiUnit::Pixels
+            };
+            let values: Vec<(f32, RoiUnit)> = coords
+                .split(',')
+                .map(|value| parse_bound(value, unitless))
+                .collect::<Result<_, _>>()
+                .map_err(invalid)?;
+            let [x, y, w, h]: [(f32, RoiUnit); 4] = values
+                .try_into()
+                .map_err(|_| invalid("expected four values: x,y,width,height"))?;
+            let unit = x.1;
+            if [y, w, h].iter().any(|(_, u)| *u != unit) {
+                return Err(invalid(
+                    "values must be all percentages, all fractions or all pixels, not a mix",
+                ));
+            }
+            Ok(Roi {
+                name,
+                bounds: [x.0, y.0, w.0, h.0],
+                unit,
+            })
+        })
+        .collect()
+}
+
+/// Deserialize an optional `roi` query parameter with [parse_rois].
+pub fn deserialize_rois<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<Roi>>, D::Error> {
+    let value: Option<String> = Option::deserialize(deserializer)?;
+    value
+        .map(|s| parse_rois(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use rten_imageproc::Rect;
+
+    use super::{parse_rois, Roi, RoiUnit};
+
+    fn roi(name: &str, bounds: [f32; 4], unit: RoiUnit) -> Roi {
+        Roi {
+            name: name.to_string(),
+            bounds,
+            unit,
+        }
+    }
+
+    #[test]
+    fn test_parse_rois() {
+        let rois = parse_rois("subtitles:0%,80%,100%,20%; ticker:10,20,300px,40px").unwrap();
+        assert_eq!(
+            rois,
+            [
+                roi("subtitles", [0., 80., 100., 20.], RoiUnit::Percent),
+                roi("ticker", [10., 20., 300., 40.], RoiUnit::Pixels),
+            ]
+        );
+
+        // Unnamed regions, with spaces and an empty trailing entry.
+        let rois = parse_rois(" 1, 2, 3, 4 ;12.5 %,0%,50%,50%;").unwrap();
+        assert_eq!(
+            rois,
+            [
+                roi("roi0", [1., 2., 3., 4.], RoiUnit::Pixels),
+                roi("roi1", [12.5, 0., 50., 50.], RoiUnit::Percent),
+            ]
+        );
+
+        // Values are fractions if any has a decimal point.
+        let rois = parse_rois("0,0.8,1,.2;1.0,1,1,1").unwrap();
+        assert_eq!(
+            rois,
+            [
+                roi("roi0", [0., 0.8, 1., 0.2], RoiUnit::Fraction),
+                roi("roi1", [1., 1., 1., 1.], RoiUnit::Fraction),
+            ]
+        );
+
+        assert_eq!(parse_rois("").unwrap(), []);
+    }
+
+    #[test]
+    fn test_parse_rois_invalid() {
+        for (spec, reason) in [
+            (":1,2,3,4", "the name must not be empty"),
+            ("a:1,2,3", "expected four values"),
+            ("a:1,2,3,4,5", "expected four values"),
+            ("a:0%,80%,100,20", "not a mix"),
+            ("a:0,0,0.5,50%", "not a mix"),
+            ("a:0,0,0.5,10px", "not a mix"),
+            ("a:0,0,0.5,1.5", "between 0 and 1"),
+            ("a:0,0,10.5px,10px", "whole numbers of pixels"),
+            ("a:-1,0,10,10", "whole numbers of pixels"),
+            ("a:x%,0%,10%,10%", "expected a number"),
+            ("a:-5%,0%,10%,10%", "must not be negative"),
+        ] {
+            let err = parse_rois(spec).unwrap_err().to_string();
+            assert!(err.contains(reason), "{}: {}", spec, err);
+        }
+    }
+
+    #[test]
+    fn test_roi_to_rect() {
+        // Percentages of the image size.
+        let rect = roi("a", [0., 80., 100., 20.], RoiUnit::Percent).to_rect(200, 100);
+        assert_eq!(rect, Some(Rect::from_tlbr(80, 0, 100, 200)));
+
+        // Fractions of the image size.
+        let rect = roi("a", [0.25, 0.5, 0.5, 0.5], RoiUnit::Fraction).to_rect(200, 100);
+        assert_eq!(rect, Some(Rect::from_tlbr(50, 50, 100, 150)));
+
+        // Pixels.
+        let rect = roi("a", [10., 20., 30., 40.], RoiUnit::Pixels).to_rect(200, 100);
+        assert_eq!(rect, Some(Rect::from_tlbr(20, 10, 60, 40)));
+
+        // Clipped to the image.
+        let rect = roi("a", [150., 50., 100., 100.], RoiUnit::Pixels).to_rect(200, 100);
+        assert_eq!(rect, Some(Rect::from_tlbr(50, 150, 100, 200)));
+        let rect = roi("a", [50., 50., 100., 100.], RoiUnit::Percent).to_rect(200, 100);
+        assert_eq!(rect, Some(Rect::from_tlbr(50, 100, 100, 200)));
+
+        // Outside the image, or empty.
+        assert_eq!(
+            roi("a", [200., 0., 10., 10.], RoiUnit::Pixels).to_rect(200, 100),
+            None
+        );
+        assert_eq!(
+            roi("a", [10., 10., 0., 10.], RoiUnit::Percent).to_rect(200, 100),
+            None
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use ocrs::TextItem;
+use rten_imageproc::Rect;
+use serde::Serialize;
+
+use crate::output::{BoxOutput, RecognizedLine};
+
+/// A line of text that appears in a run of consecutive frames.
+#[derive(Clone, Debug, Serialize)]
+pub struct TextSpan {
+    /// Text of the occurrence with the highest confidence, or of the first
+    /// occurrence if confidence was not scored.
+    pub text: String,
+
+    /// Index of the first frame the text appears in.
+    pub start_frame: usize,
+
+    /// Index of the last frame the text appears in.
+    pub end_frame: usize,
+
+    /// Timestamp of the first frame, in seconds, for video input.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<f64>,
+
+    /// Timestamp of the last frame, in seconds, for video input.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_time: Option<f64>,
+
+    /// Union of the line's bounding boxes across all frames.
+    pub bounding_box: BoxOutput,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+/// A span that may continue in the next frame.
+struct OpenSpan {
+    span: TextSpan,
+
+    /// Bounding box of the line in the most recent frame.
+    last_rect: Rect,
+    bounds: Rect,
+}
+
+/// Merges lines that repeat across consecutive frames into [TextSpan]s.
+///
+/// A line continues a span from the previous frame if the texts are similar
+/// and their bounding boxes overlap. Lines that do not continue a span start
+/// a new one, and spans that are not continued are closed.
+pub struct SpanTracker {
+    min_similarity: f32,
+    min_overlap: f32,
+    open: Vec<OpenSpan>,
+    closed: Vec<OpenSpan>,
+}
+
+impl SpanTracker {
+    /// Create a tracker.
+    ///
+    /// `min_similarity` is the minimum [text_similarity] and `min_overlap`
+    /// the minimum [box_overlap] for a line to continue a span.
+    pub fn new(min_similarity: f32, min_overlap: f32) -> SpanTracker {
+        SpanTracker {
+            min_similarity,
+            min_overlap,
+            open: Vec::new(),
+            closed: Vec::new(),
+        }
+    }
+
+    /// Add the lines found in the next frame.
+    ///
+    /// Frames must be added in order. A frame that failed to process should
+    /// be added with no lines, which ends all open spans.
+    pub fn push_frame(&mut self, index: usize, timestamp: Option<f64>, lines: &[RecognizedLine]) {
+        let mut previous: Vec<Option<OpenSpan>> = self.open.drain(..).map(Some).collect();
+
+        for line in lines {
+            let text = line.line.to_string();
+            if text.trim().is_empty() {
+                continue;
+            }
+            let rect = line.line.bounding_rect();
+            let confidence = line.confidence();
+
+            // Continue the most similar span from the previous frame, if any
+            // is close enough.
+            let best_match = previous
+                .iter()
+                .enumerate()
+                .filter_map(|(i, span)| {
+                    let span = span.as_ref()?;
+                    if span.span.region != line.region
+                        || box_overlap(span.last_rect, rect) < self.min_overlap
+                    {
+                        return None;
+                    }
+                    let similarity = text_similarity(&span.span.text, &text);
+                    (similarity >= self.min_similarity).then_some((i, similarity))
+                })
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(i, _)| i);
+
+            let open_span = match best_match.and_then(|i| previous[i].take()) {
+                Some(mut open_span) => {
+                    let span = &mut open_span.span;
+                    span.end_frame = index;
+                    span.end_time = timestamp;
+                    if confidence > span.confidence {
+                        span.text = text;
+                        span.confidence = confidence;
+                    }
+                    open_span.last_rect = rect;
+                    open_span.bounds = open_span.bounds.union(rect);
+                    open_span
+                }
+                None => OpenSpan {
+                    span: TextSpan {
+                        text,
+                        start_frame: index,
+                        end_frame: index,
+                        start_time: timestamp,
+                        end_time: timestamp,
+                        bounding_box: rect.into(),
+                        confidence,
+                        region: line.region.clone(),
+                    },
+                    last_rect: rect,
+                    bounds: rect,
+                },
+            };
+            self.open.push(open_span);
+        }
+
+        self.closed.extend(previous.into_iter().flatten());
+    }
+
+    /// End all open spans and return every span, ordered by the frame it
+    /// starts in.
+    pub fn finish(mut self) -> Vec<TextSpan> {
+        self.closed.append(&mut self.open);
+        let mut spans: Vec<TextSpan> = self
+            .closed
+            .into_iter()
+            .map(|open_span| TextSpan {
+                bounding_box: open_span.bounds.into(),
+                ..open_span.span
+            })
+            .collect();
+        spans.sort_by_key(|span| (span.start_frame, span.bounding_box.y, span.bounding_box.x));
+        spans
+    }
+}
+
+/// Return the similarity of two strings between 0 (nothing in common) and 1
+/// (identical), based on their edit distance.
+///
+/// Case and surrounding whitespace are ignored.
+pub fn text_similarity(a: &str, b: &str) -> f32 {
+    let a: Vec<char> = a.trim().to_lowercase().chars().collect();
+    let b: Vec<char> = b.trim().to_lowercase().chars().collect();
+    let max_len = a.len().max(b.len());
+    if max_len == 0 {
+        return 1.;
+    }
+    1. - levenshtein(&a, &b) as f32 / max_len as f32
+}
+
+/// Return the number of single-character insertions, deletions and
+/// substitutions needed to turn `a` into `b`.
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut prev_row: Vec<usize> = (0..=b.len()).collect();
+    let mut row = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev_row[j] + usize::from(ca != cb);
+            row[j + 1] = substitution.min(prev_row[j + 1] + 1).min(row[j] + 1);
+        }
+        std::mem::swap(&mut prev_row, &mut row);
+    }
+    prev_row[b.len()]
+}
+
+/// Return the area of the intersection of two boxes as a fraction of the
+/// smaller box.
+///
+/// This is used instead of intersection-over-union so that a line whose
+/// width changes as characters are misread still matches.
+pub fn box_overlap(a: Rect, b: Rect) -> f32 {
+    let area = |r: Rect| r.width().max(0) as f32 * r.height().max(0) as f32;
+    let min_area = area(a).min(area(b));
+    if min_area == 0. {
+        return 0.;
+    }
+    area(a.intersection(b)) / min_area
+}
+
+#[cfg(test)]
+mod tests {
+    use ocrs::{TextChar, TextLine};
+    use rten_imageproc::Rect;
+
+    use super::{box_overlap, levenshtein, text_similarity, SpanTracker};
+    use crate::output::RecognizedLine;
+
+    /// Create a line with 10x20 pixel characters, starting at `(left, top)`.
+    fn line(text: &str, left: i32, top: i32, confidence: Option<f32>) -> RecognizedLine {
+        let chars: Vec<TextChar> = text
+            .chars()
+            .enumerate()
+            .map(|(i, char)| {
+                let x = left + i as i32 * 10;
+                TextChar {
+                    char,
+                    rect: Rect::from_tlbr(top, x, top + 20, x + 10),
+                }
+            })
+            .collect();
+        let char_confidences = confidence.map(|c| vec![c; chars.len()]);
+        RecognizedLine {
+            line: TextLine::new(chars),
+            char_confidences,
+            region: None,
+        }
+    }
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        for (a, b, distance) in [
+            ("", "", 0),
+            ("abc", "", 3),
+            ("", "abc", 3),
+            ("abc", "abc", 0),
+            ("kitten", "sitting", 3),
+            ("flaw", "lawn", 2),
+            ("hello world", "hel1o wor1d", 2),
+        ] {
+            assert_eq!(levenshtein(&chars(a), &chars(b)), distance, "{} {}", a, b);
+            assert_eq!(levenshtein(&chars(b), &chars(a)), distance, "{} {}", b, a);
+        }
+    }
+
+    #[test]
+    fn test_text_similarity() {
+        assert_eq!(text_similarity("", ""), 1.);
+        assert_eq!(text_similarity(" Hello ", "hello"), 1.);
+        assert_eq!(text_similarity("abcd", "abce"), 0.75);
+        assert_eq!(text_similarity("abc", "xyz"), 0.);
+    }
+
+    #[test]
+    fn test_box_overlap() {
+        let a = Rect::from_tlbr(0, 0, 10, 10);
+        assert_eq!(box_overlap(a, a), 1.);
+
+        // Half of `a` overlaps `b`.
+        let b = Rect::from_tlbr(0, 5, 10, 15);
+        assert_eq!(box_overlap(a, b), 0.5);
+        assert_eq!(box_overlap(b, a), 0.5);
+
+        // A box inside a larger one overlaps it fully.
+        let inner = Rect::from_tlbr(2, 2, 4, 4);
+        assert_eq!(box_overlap(a, inner), 1.);
+
+        // Disjoint and empty boxes.
+        assert_eq!(box_overlap(a, Rect::from_tlbr(20, 20, 30, 30)), 0.);
+        assert_eq!(box_overlap(a, Rect::from_tlbr(5, 5, 5, 5)), 0.);
+    }
+
+    #[test]
+    fn test_span_tracker() {
+        let mut tracker = SpanTracker::new(0.7, 0.5);
+
+        // A line that is misread in one frame continues its span, and the
+        // text with the highest confidence is kept.
+        tracker.push_frame(0, Some(0.), &[line("Hello world", 0, 0, Some(0.5))]);
+        tracker.push_frame(
+            1,
+            Some(0.5),
+            &[
+                line("Hello wor1d", 2, 1, Some(0.4)),
+                line("Other text", 0, 50, Some(0.9)),
+            ],
+        );
+        tracker.push_frame(2, Some(1.), &[line("hello world", 4, 0, Some(0.9))]);
+
+        // A frame without lines ends all spans.
+        tracker.push_frame(3, Some(1.5), &[]);
+        tracker.push_frame(4, Some(2.), &[line("Hello world", 0, 0, None)]);
+
+        // A line in a different place does not continue a span.
+        tracker.push_frame(5, Some(2.5), &[line("Hello world", 0, 100, None)]);
+
+        let spans = tracker.finish();
+        let summary: Vec<_> = spans
+            .iter()
+            .map(|span| (span.text.as_str(), span.start_frame, span.end_frame))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("hello world", 0, 2),
+                ("Other text", 1, 1),
+                ("Hello world", 4, 4),
+                ("Hello world", 5, 5),
+            ]
+        );
+
+        let first = &spans[0];
+        assert_eq!(first.start_time, Some(0.));
+        assert_eq!(first.end_time, Some(1.));
+        assert_eq!(first.confidence, Some(0.9));
+
+        // The bounding box is the union of the line's boxes in all frames.
+        let bbox = first.bounding_box;
+        assert_eq!((bbox.x, bbox.y, bbox.width, bbox.height), (0, 0, 114, 21));
+    }
+
+    #[test]
+    fn test_span_tracker_regions() {
+        let mut tracker = SpanTracker::new(0.7, 0.5);
+        let mut other_region = line("Hello world", 0, 0, None);
+        other_region.region = Some("ticker".to_string());
+
+        tracker.push_frame(0, None, &[line("Hello world", 0, 0, None)]);
+        tracker.push_frame(1, None, &[other_region]);
+
+        let spans = tracker.finish();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].region, None);
+        assert_eq!(spans[1].region.as_deref(), Some("ticker"));
+    }
+}
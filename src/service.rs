@@ -0,0 +1,219 @@
+use std::fmt;
+
+use anyhow::{anyhow, Context};
+use rten_tensor::prelude::*;
+use rten_tensor::NdTensor;
+
+use crate::confidence::ConfidenceScorer;
+use crate::decode::{decode_image, image_to_tensor, DecodeError};
+use crate::engine::{EngineConfig, ModelFile, OcrEngines};
+use crate::frame_cache::FrameCache;
+use crate::output::{format_text_output, OcrResult, PageInfo};
+use crate::pipeline::{run_ocr, run_ocr_regions, OcrOptions, OcrPage, PipelineError};
+use crate::roi::Roi;
+use crate::sequence::text_similarity;
+use crate::timing::time_stage;
+
+/// Reasons an image could not be processed.
+#[derive(Debug)]
+pub enum OcrError {
+    /// The image data could not be decoded.
+    Decode(DecodeError),
+
+    /// The image was decoded, but OCR failed.
+    Pipeline(PipelineError),
+}
+
+impl fmt::Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OcrError::Decode(err) => write!(f, "{}", err),
+            OcrError::Pipeline(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for OcrError {}
+
+impl From<DecodeError> for OcrError {
+    fn from(err: DecodeError) -> Self {
+        OcrError::Decode(err)
+    }
+}
+
+impl From<PipelineError> for OcrError {
+    fn from(err: PipelineError) -> Self {
+        OcrError::Pipeline(err)
+    }
+}
+
+/// Loaded models and everything else needed to run OCR on images.
+///
+/// Loading is slow and may download the models, so a service should be
+/// created once and shared. All methods take `&self` and can be called from
+/// several threads at once.
+pub struct OcrService {
+    engines: OcrEngines,
+    scorer: ConfidenceScorer,
+    frame_cache: FrameCache,
+}
+
+impl OcrService {
+    /// Fetch and load all models.
+    pub fn new(config: &EngineConfig) -> Result<OcrService, anyhow::Error> {
+        let engines = OcrEngines::load(config)?;
+        let scorer = ConfidenceScorer::from_model(engines.load_recognition_model()?)
+            .context("Failed to initialize confidence scorer")?;
+
+        Ok(OcrService {
+            engines,
+            scorer,
+            frame_cache: FrameCache::new(0, 0),
+        })
+    }
+
+    /// Reuse the results of recently processed frames for similar frames.
+    /// The cache is disabled by default.
+    pub fn with_frame_cache(mut self, frame_cache: FrameCache) -> OcrService {
+        self.frame_cache = frame_cache;
+        self
+    }
+
+    /// Return the text detection model that was loaded.
+    pub fn detection_model(&self) -> &ModelFile {
+        self.engines.detection_model()
+    }
+
+    /// Return the text recognition model that was loaded.
+    pub fn recognition_model(&self) -> &ModelFile {
+        self.engines.recognition_model()
+    }
+
+    /// Decode an image and run OCR on it.
+    ///
+    /// `content_type` is used to identify the image format if it cannot be
+    /// determined from the data. If `regions` is given, only those parts of
+    /// the image are processed.
+    pub fn ocr_image(
+        &self,
+        data: &[u8],
+        content_type: Option<&str>,
+        regions: Option<&[Roi]>,
+        opts: &OcrOptions,
+    ) -> Result<OcrResult, OcrError> {
+        let decoded = time_stage("decode", || decode_image(data, content_type))?;
+        let color_img = image_to_tensor(decoded.image);
+        let mut result = self.ocr_tensor(&color_img, regions, opts)?;
+        result.page.exif_orientation = decoded.orientation;
+        Ok(result)
+    }
+
+    /// Run OCR on an HWC RGB image.
+    ///
+    /// The image, or each region of it, is looked up in the frame cache first
+    /// and only processed if no similar frame was seen recently.
+    pub fn ocr_tensor(
+        &self,
+        color_img: &NdTensor<u8, 3>,
+        regions: Option<&[Roi]>,
+        opts: &OcrOptions,
+    ) -> Result<OcrResult, PipelineError> {
+        let [height, width, _] = color_img.shape();
+        let run = |img: &NdTensor<u8, 3>| {
+            self.frame_cache
+                .get_or_insert_with(img, opts, || self.ocr_page(img, opts))
+        };
+        let page = match regions {
+            Some(regions) => run_ocr_regions(color_img, regions, run)?,
+            None => run(color_img)?,
+        };
+        Ok(OcrResult {
+            page: PageInfo {
+                width: width as u32,
+                height: height as u32,
+                exif_orientation: None,
+                rotation: page.rotation,
+            },
+            lines: page.lines,
+        })
+    }
+
+    /// Detect and recognize text in an HWC RGB image, bypassing the frame
+    /// cache.
+    pub fn ocr_page(
+        &self,
+        color_img: &NdTensor<u8, 3>,
+        opts: &OcrOptions,
+    ) -> Result<OcrPage, PipelineError> {
+        run_ocr(&self.engines, &self.scorer, color_img, opts)
+    }
+
+    /// Run OCR on a built-in sample image to check that the models work.
+    ///
+    /// This checks that every stage of the pipeline runs and that the text
+    /// read is close to [SAMPLE_TEXT]. A few misread characters are
+    /// tolerated, since the sample uses a crude pixel font.
+    pub fn self_test(&self) -> Result<(), anyhow::Error> {
+        let opts = OcrOptions {
+            score_confidence: true,
+            ..Default::default()
+        };
+        let page = self.ocr_page(&sample_image(), &opts)?;
+        let text = format_text_output(&page.lines).replace('\n', " ");
+        let similarity = text_similarity(&text, SAMPLE_TEXT);
+        if similarity < MIN_SAMPLE_SIMILARITY {
+            return Err(anyhow!(
+                "Read \"{}\" from the sample image instead of \"{}\"",
+                text,
+                SAMPLE_TEXT
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Text drawn in the self-test image.
+const SAMPLE_TEXT: &str = "FRAME OCR";
+
+/// Minimum [text_similarity] of the text read from the sample image to
+/// [SAMPLE_TEXT] for the self-test to pass.
+const MIN_SAMPLE_SIMILARITY: f32 = 0.6;
+
+/// Size in pixels of each dot of the sample font.
+const SAMPLE_SCALE: usize = 6;
+
+/// 5x7 bitmaps of the letters in [SAMPLE_TEXT]. Each row is 5 bits, most
+/// significant bit on the left.
+fn glyph(c: char) -> [u8; 7] {
+    match c {
+        'A' => [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        _ => [0; 7],
+    }
+}
+
+/// Render [SAMPLE_TEXT] as black text on a white HWC RGB image.
+fn sample_image() -> NdTensor<u8, 3> {
+    // Each character cell is 6 dots wide, including one dot of spacing.
+    let margin = 4 * SAMPLE_SCALE;
+    let width = SAMPLE_TEXT.len() * 6 * SAMPLE_SCALE + 2 * margin;
+    let height = 7 * SAMPLE_SCALE + 2 * margin;
+    let chars: Vec<char> = SAMPLE_TEXT.chars().collect();
+
+    NdTensor::from_fn([height, width, 3], |[y, x, _]| {
+        let (Some(x), Some(y)) = (x.checked_sub(margin), y.checked_sub(margin)) else {
+            return 255;
+        };
+        let (col, row) = (x / SAMPLE_SCALE, y / SAMPLE_SCALE);
+        let (cell, dot) = (col / 6, col % 6);
+        match chars.get(cell) {
+            Some(&c) if row < 7 && dot < 5 && glyph(c)[row] & (0x10 >> dot) != 0 => 0,
+            _ => 255,
+        }
+    })
+}
@@ -0,0 +1,25 @@
+//! Timing of the stages of the OCR pipeline.
+//!
+//! Each stage runs in an `info` span named [STAGE_SPAN] whose `stage` field
+//! names the stage, eg. `detect_words`. Applications can record stage
+//! durations with a `tracing` layer that handles these spans.
+
+use std::time::Instant;
+
+use tracing::{debug, info_span};
+
+/// Name of the span that each pipeline stage runs in.
+pub const STAGE_SPAN: &str = "stage";
+
+/// Run `f` in a span for the pipeline stage `stage`, and log its duration.
+pub fn time_stage<T>(stage: &str, f: impl FnOnce() -> T) -> T {
+    let span = info_span!(STAGE_SPAN, stage, otel.name = stage);
+    let _guard = span.enter();
+    let start = Instant::now();
+    let result = f();
+    debug!(
+        duration_ms = start.elapsed().as_secs_f64() * 1000.,
+        "Stage finished"
+    );
+    result
+}